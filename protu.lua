@@ -5,26 +5,39 @@ local sleep = sleep
 local TURTLE_ID = "/19"
 
 local API = "http://192.168.1.92:8080/"
+-- Must match turtle_token (or this turtle's entry in turtle_tokens) in Rocket.toml
+local TOKEN = ""
 
-local function cresp(code, output)
+local HEADERS = {
+        ["X-Turtle-Token"] = TOKEN,
+}
+
+-- Handed out by the server every time we register
+local SESSION = ""
+
+-- seq is sent back as it came with the command, so the server can tell which command this answers
+local function cresp(code, output, seq)
         return json.encode({
                 code = code,
                 out = output,
+                seq = seq,
         })
 end
 
 local function cmdcomplete(body)
         local _, _ = http.post({
-                url = API .. "cmdcomplete" .. TURTLE_ID,
+                url = API .. "cmdcomplete" .. TURTLE_ID .. "?session=" .. SESSION,
                 headers = {
                         ["Content-Type"] = "application/json",
+                        ["X-Turtle-Token"] = TOKEN,
                 },
                 body = body,
         })
 end
 
+-- The server holds this request open until it has a command for us (or answers WAIT)
 local function next()
-        local resp, err = http.get(API .. "next" .. TURTLE_ID)
+        local resp, err = http.get(API .. "next" .. TURTLE_ID .. "?session=" .. SESSION, HEADERS)
         if err ~= nil then
                 return
         end
@@ -33,25 +46,156 @@ local function next()
         return resp.readAll()
 end
 
+-- Whether s matches pattern, in which * stands for any number of characters
+local function glob(pattern, s)
+        local escaped = pattern:gsub("[%^%$%(%)%%%.%[%]%+%-%?]", "%%%0"):gsub("%*", ".*")
+        return s:match("^" .. escaped .. "$") ~= nil
+end
+
+-- Whether the block matches one of the patterns, block IDs or tags starting
+-- with #, see NavConfig::never_digs
+local function matches_block(patterns, block)
+        for _, p in ipairs(patterns) do
+                if p:sub(1, 1) == "#" then
+                        for tag, _ in pairs(block.tags or {}) do
+                                if glob(p:sub(2), tag) then
+                                        return true
+                                end
+                        end
+                elseif glob(p, block.name) then
+                        return true
+                end
+        end
+        return false
+end
+
+-- Digs unless the block matches one of c.never. The message has to match
+-- NEVER_DIG_MSG in src/turtle_core/data.rs
+local function dig_unless(inspect, dig)
+        return function(c)
+                local found, block = inspect()
+                if found and matches_block(c.never, block) then
+                        return false, "Never dig: " .. block.name
+                end
+                return dig()
+        end
+end
+
+-- One entry per command the server can send, see TurtCommand in src/turtle_core/command.rs
+local COMMANDS = {
+        forward = function() return turtle.forward() end,
+        back = function() return turtle.back() end,
+        up = function() return turtle.up() end,
+        down = function() return turtle.down() end,
+        turn_left = function() return turtle.turnLeft() end,
+        turn_right = function() return turtle.turnRight() end,
+        dig = function() return turtle.dig() end,
+        dig_up = function() return turtle.digUp() end,
+        dig_down = function() return turtle.digDown() end,
+        dig_unless = dig_unless(turtle.inspect, turtle.dig),
+        dig_up_unless = dig_unless(turtle.inspectUp, turtle.digUp),
+        dig_down_unless = dig_unless(turtle.inspectDown, turtle.digDown),
+        inspect = function() return turtle.inspect() end,
+        inspect_up = function() return turtle.inspectUp() end,
+        inspect_down = function() return turtle.inspectDown() end,
+        place = function() return turtle.place() end,
+        place_up = function() return turtle.placeUp() end,
+        place_down = function() return turtle.placeDown() end,
+        suck = function(c) return turtle.suck(c.count) end,
+        suck_up = function(c) return turtle.suckUp(c.count) end,
+        suck_down = function(c) return turtle.suckDown(c.count) end,
+        drop = function(c) return turtle.drop(c.count) end,
+        drop_up = function(c) return turtle.dropUp(c.count) end,
+        drop_down = function(c) return turtle.dropDown(c.count) end,
+        detect = function() return turtle.detect() end,
+        detect_up = function() return turtle.detectUp() end,
+        detect_down = function() return turtle.detectDown() end,
+        compare = function() return turtle.compare() end,
+        compare_up = function() return turtle.compareUp() end,
+        compare_down = function() return turtle.compareDown() end,
+        attack = function() return turtle.attack() end,
+        attack_up = function() return turtle.attackUp() end,
+        attack_down = function() return turtle.attackDown() end,
+        select = function(c) return turtle.select(c.slot) end,
+        get_item_detail = function(c) return turtle.getItemDetail(c.slot) end,
+        get_item_count = function(c) return turtle.getItemCount(c.slot) end,
+        get_item_space = function(c) return turtle.getItemSpace(c.slot) end,
+        get_selected_slot = function() return turtle.getSelectedSlot() end,
+        compare_to = function(c) return turtle.compareTo(c.slot) end,
+        transfer_to = function(c) return turtle.transferTo(c.slot, c.count) end,
+        equip_left = function() return turtle.equipLeft() end,
+        equip_right = function() return turtle.equipRight() end,
+        -- Only exists with a crafting table equipped, run() reports the error otherwise
+        craft = function(c) return turtle.craft(c.limit) end,
+        get_fuel_level = function() return turtle.getFuelLevel() end,
+        get_fuel_limit = function() return turtle.getFuelLimit() end,
+        refuel = function(c) return turtle.refuel(c.count) end,
+        gps_locate = function() return gps.locate() end,
+        print = function(c) return print(c.msg) end,
+}
+
+-- Calls func, returns the code and output to send back. Errors are reported
+-- instead of stopping the script.
+local function call(func, c)
+        local res = { pcall(func, c) }
+        if not res[1] then
+                return -1, res[2]
+        end
+        table.remove(res, 1)
+        return 0, res
+end
+
+-- Runs a single command, returns the code and output to send back
+local function run(c)
+        if c.cmd == "eval" then
+                local func, _ = load("return " .. c.code)
+                if not func then
+                        return -2, "Bad function: " .. c.code
+                end
+                return call(func)
+        end
+        local func = COMMANDS[c.cmd]
+        if not func then
+                return -2, "Unknown command: " .. tostring(c.cmd)
+        end
+        return call(func, c)
+end
+
+-- Runs each command of a batch in order. Returns the result of each command
+-- that was executed.
+local function run_batch(c)
+        local results = {}
+        for _, sub in ipairs(c.cmds) do
+                local code, out = run(sub)
+                table.insert(results, { code = code, out = out })
+                if c.stop_on_fail and (code ~= 0 or out[1] == false) then
+                        break
+                end
+        end
+        return results
+end
+
 local function register()
         print("Trying to register...")
         while true do
                 local resp, err = http.post({
                         url = API .. "register" .. TURTLE_ID,
+                        headers = HEADERS,
                         body = ""
                 })
                 if err == nil then
+                        SESSION = resp.readAll()
                         break
                 end
                 sleep(1)
         end
-        print("Registered!")
+        print("Registered! Session: " .. SESSION)
 end
 
 while true do
         register()
 
-        local cmd, func, rtnval
+        local cmd
         local i = 0;
         while true do
                 cmd = next()
@@ -60,24 +204,23 @@ while true do
                         break
                 else
                         print("iter: " .. i .. " | cmd: " .. cmd)
+                        local ok, c = false, nil
+                        if cmd ~= "WAIT" then
+                                ok, c = pcall(json.decode, cmd)
+                        end
                         if cmd == "WAIT" then
+                                -- nothing to do, ask again straight away
+                        elseif not ok or type(c) ~= "table" then
+                                cmdcomplete(cresp(-1, "Bad request. Unable to read command."))
                                 sleep(0.1)
-                        elseif cmd == "EXIT" then
-                                cmdcomplete(cresp(0, "Turtle leaving the chat."))
+                        elseif c.cmd == "exit" then
+                                cmdcomplete(cresp(0, "Turtle leaving the chat.", c.seq))
                                 break
-                        elseif cmd == nil then
-                                cmdcomplete(cresp(-1, "Bad request. Unable to execute code."))
-                                sleep(0.1)
+                        elseif c.cmd == "batch" then
+                                cmdcomplete(cresp(0, run_batch(c), c.seq))
                         else
-                                cmd = "return " .. cmd
-                                func, _ = load(cmd)
-                                if not func then
-                                        cmdcomplete(cresp(-2, "Bad function: " .. cmd))
-                                        -- continue
-                                else
-                                        rtnval = { func() }
-                                        cmdcomplete(cresp(0, rtnval))
-                                end
+                                local code, out = run(c)
+                                cmdcomplete(cresp(code, out, c.seq))
                         end
                         i = i + 1
                 end
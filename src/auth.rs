@@ -0,0 +1,109 @@
+//! Request guards that stop anyone else on the network from posing as a turtle or handing out work.
+//!
+//! Turtles send their token in the `X-Turtle-Token` header. A token can be configured per turtle
+//! in `Rocket.toml` under `turtle_tokens`, otherwise the server-wide `turtle_token` is used.
+//! Whoever creates jobs, assigns turtles, exports the map or reads the turtles' status sends
+//! `operator_token` in the `X-Operator-Token` header. The server refuses to start without tokens,
+//! unless `insecure` is set, in which case every request is let through.
+use std::collections::HashMap;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+use crate::TurtleIdentifier;
+
+pub const TOKEN_HEADER: &str = "X-Turtle-Token";
+pub const OPERATOR_TOKEN_HEADER: &str = "X-Operator-Token";
+
+#[derive(Deserialize, Debug, Default)]
+pub struct AuthConfig {
+    #[serde(default)]
+    turtle_token: Option<String>,
+    /// Keyed by turtle ID.
+    #[serde(default)]
+    turtle_tokens: HashMap<String, String>,
+    #[serde(default)]
+    operator_token: Option<String>,
+    /// Lets every request through if no tokens are set, instead of refusing to start.
+    #[serde(default)]
+    insecure: bool,
+}
+
+impl AuthConfig {
+    pub fn enabled(&self) -> bool {
+        self.turtle_token.is_some() || !self.turtle_tokens.is_empty()
+    }
+
+    /// Why the server shouldn't start with this config, if it shouldn't.
+    pub fn check(&self) -> Result<(), &'static str> {
+        match (self.enabled(), self.operator_token.is_some(), self.insecure) {
+            (true, true, _) | (_, _, true) => Ok(()),
+            (false, _, false) => Err("No turtle_token is configured, set one or set insecure = true."),
+            (true, false, false) => Err("No operator_token is configured, set one or set insecure = true."),
+        }
+    }
+
+    pub fn insecure(&self) -> bool {
+        self.insecure
+    }
+
+    fn token_for(&self, turtleid: TurtleIdentifier) -> Option<&str> {
+        self.turtle_tokens
+            .get(&turtleid.to_string())
+            .or(self.turtle_token.as_ref())
+            .map(|t| t.as_str())
+    }
+}
+
+/// Only succeeds if the request carries the token of the turtle in the first path parameter.
+pub struct TurtleAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TurtleAuth {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let conf = match req.rocket().state::<AuthConfig>() {
+            Some(conf) => conf,
+            None => return Outcome::Failure((Status::InternalServerError, "Auth is not configured.")),
+        };
+        if !conf.enabled() {
+            return match conf.insecure {
+                true => Outcome::Success(TurtleAuth),
+                false => Outcome::Failure((Status::Unauthorized, "No turtle tokens are configured.")),
+            };
+        }
+
+        let turtleid = match req.param::<TurtleIdentifier>(0) {
+            Some(Ok(turtleid)) => turtleid,
+            _ => return Outcome::Failure((Status::BadRequest, "No turtle ID.")),
+        };
+        let token = req.headers().get_one(TOKEN_HEADER);
+        match (conf.token_for(turtleid), token) {
+            (Some(expected), Some(token)) if expected == token => Outcome::Success(TurtleAuth),
+            (None, _) => Outcome::Failure((Status::Unauthorized, "No token for this turtle.")),
+            _ => Outcome::Failure((Status::Unauthorized, "Invalid token.")),
+        }
+    }
+}
+
+/// Only succeeds if the request carries the operator token.
+pub struct OperatorAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OperatorAuth {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let conf = match req.rocket().state::<AuthConfig>() {
+            Some(conf) => conf,
+            None => return Outcome::Failure((Status::InternalServerError, "Auth is not configured.")),
+        };
+        let token = req.headers().get_one(OPERATOR_TOKEN_HEADER);
+        match (conf.operator_token.as_deref(), token) {
+            (Some(expected), Some(token)) if expected == token => Outcome::Success(OperatorAuth),
+            (None, _) if conf.insecure => Outcome::Success(OperatorAuth),
+            (None, _) => Outcome::Failure((Status::Unauthorized, "No operator token is configured.")),
+            _ => Outcome::Failure((Status::Unauthorized, "Invalid operator token.")),
+        }
+    }
+}
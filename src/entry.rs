@@ -1,216 +1,156 @@
 use modelutils_rs::{DEG2RAD, float};
 use modelutils_rs::coords::Order;
-use modelutils_rs::model2arr::{Block, CoordXZ, model_2_arr, uint};
+use modelutils_rs::model2arr::{Block, CoordXZ, model_2_arr};
 use modelutils_rs::model::{Faces, Model, Points};
 use modelutils_rs::vec3::Vec3;
+use rocket::tokio::task;
+use crate::jobs::{ChunkDiggerJob, JobConfig, JobId, JobKind, ModelBuilderJob};
 use crate::scripts::chunk_digger::{ChunkDigger, ChunkDiggerConfig};
 use crate::scripts::model_builder::generation::{array_model_to_nodes, centroids_to_groupings, k_means};
 use crate::scripts::model_builder::runtime::{ModelBuilder, ModelBuilderConfig};
 use crate::server::ChannelsClient;
 use crate::turtle_core::control::TurtControl;
-use crate::turtle_core::inventory::TurtBlock;
-use crate::turtle_core::navigation::{Head, Pos, PosH, TurtNavigation};
-use crate::{TurtleIdentifier, TurtleIndex};
-
-// const PATH: &str = "assets/octo.obj";
-// const ACTIVE_TURTLES: &[usize] = &[
-//     4,
-//     9,
-//     10,
-//     11,
-//     12,
-//     19,
-//     20,
-//     21
-// ];
-// const START_POS: PosH = PosH {
-//     x: -1524,
-//     y: 63,
-//     z: -459,
-//     h: Head::N,
-// };
-// const DEPTH: i64 = 64;
-// const WIDTH: i64 = 64 / 8;
-// const HEIGHT: i64 = 108 - 63;
-//
-// fn id_to_i(turtleid: usize) -> usize {
-//     let mut v = None;
-//     for (i, t_id) in ACTIVE_TURTLES.iter().enumerate() {
-//         if t_id == &turtleid {
-//             v = Some(i);
-//             break;
-//         }
-//     }
-//     match v {
-//         Some(v) => v,
-//         None => panic!("Rip."),
-//     }
-// }
-//
-//
-// pub fn turtle_registered(identifier: TurtleIdentifier, channels_client: ChannelsClient) {
-//     let turt = TurtControl::new(
-//         channels_client.0.clone(),
-//         &channels_client.1);
-//     let mut nav = TurtNavigation::new(
-//         identifier,
-//         &turt,
-//         true,
-//         channels_client.0.clone(),
-//         &channels_client.1);
-//
-//     let ind = id_to_i(identifier) as i64;
-//
-//     println!("[pre] Turtle {} registered! {}", identifier, nav);
-//
-//     nav.gps_init();
-//     println!("Turtle {} registered! {}", identifier, nav);
-//
-//     let conf = ChunkDiggerConfig {
-//         p1: Pos::new(START_POS.x + ind * WIDTH, START_POS.y, START_POS.z - DEPTH),
-//         p2: Pos::new(START_POS.x + (ind + 1) * WIDTH, START_POS.y + HEIGHT, START_POS.z - DEPTH),
-//         place_floor: TurtBlock::None,
-//         chest_size: 54, // iron chest
-//         check_inv_every_n_blocks: 64,
-//     };
-//     let mut digger = ChunkDigger::init(
-//         (
-//             identifier,
-//             ind as TurtleIndex,
-//             &turt,
-//             &mut nav
-//         ), conf);
-//     digger.run();
-//
-//     turt.disconnect();
-// }
-//
-
-
-const PATH: &str = "assets/octo.obj";
-const ACTIVE_TURTLES: &[usize] = &[
-    4,
-    9,
-    10,
-    11,
-    12,
-    19,
-    20,
-    21
-];
-const SIZE: uint = 200;
-const START_POS: PosH = PosH {
-    x: -2320,
-    y: 60,
-    z: -1072,
-    h: Head::N,
-};
-
-
-const RESOLUTION: float = 100.0;
-const DIMS: (uint, uint, uint) = (SIZE, SIZE, SIZE);
-
-fn id_to_i(turtleid: usize) -> usize {
-    let mut v = None;
-    for (i, t_id) in ACTIVE_TURTLES.iter().enumerate() {
-        if t_id == &turtleid {
-            v = Some(i);
-            break;
-        }
-    }
-    match v {
-        Some(v) => v,
-        None => panic!("Rip."),
-    }
-}
-
-fn get_model(path: &str) -> Vec<(Vec<Vec<(CoordXZ, Block)>>, usize)> {
+use crate::turtle_core::error::TurtError;
+use crate::turtle_core::events::TurtEventKind;
+use crate::turtle_core::navigation::{PosH, TurtNavigation};
+use crate::{DefaultData, TurtleIdentifier};
+
+/// Splits the job's model into parts for `turtles` turtles. Fails if the model can't be loaded or
+/// has nothing to build.
+fn get_model(job: &ModelBuilderJob, turtles: usize) -> Result<Vec<(Vec<Vec<(CoordXZ, Block)>>, usize)>, TurtError> {
+    let dims = (job.size, job.size, job.size);
     let box_scale = Vec3::new(
-        DIMS.0 as float - 1.0,
-        DIMS.1 as float - 1.0,
-        DIMS.2 as float - 1.0,
+        dims.0 as float - 1.0,
+        dims.1 as float - 1.0,
+        dims.2 as float - 1.0,
     );
 
-    let (models, _materials) = modelutils_rs::load_default(path).unwrap();
-    let models = models
+    let (models, _materials) = modelutils_rs::load_default(&job.model)
+        .map_err(|e| TurtError::BadJob(format!("Couldn't load model {}: {:?}", job.model, e)))?;
+    // Only the first model in the file is built
+    let mut model = models
         .into_iter()
         .map(|m| Model::new(
             Points::from_flat_vec(m.mesh.positions),
             Faces::from_triangles(m.mesh.indices),
         ))
-        .collect::<Vec<Model>>();
-    for mut model in models.into_iter() {
-        model.rotate(Vec3::new(0.0, 225.0 * DEG2RAD, 0.0), Order::XYZ);
-        // Align model to origin
-        let bounds = model.model_dims();
-        model.mv(bounds.0 * Vec3::from_scalar(-1.0));
-        // Scale model to fit in 10x10x10 cube
-        let scale = model.scale_for_box(box_scale);
-        model.scale(Vec3::from_scalar(scale.min_val()));
-
-        let dims = model.model_dims();
-        // Convert to array
-        let arr = model_2_arr(model, DIMS, RESOLUTION);
-        let nodes = array_model_to_nodes(arr);
-        let centroids = k_means(&nodes, DIMS, ACTIVE_TURTLES.len());
-        let groupings = centroids_to_groupings(nodes, centroids, DIMS);
-
-        return groupings;
+        .next()
+        .ok_or_else(|| TurtError::BadJob(format!("There is no model in {}", job.model)))?;
+    model.rotate(Vec3::new(0.0, job.rotation * DEG2RAD, 0.0), Order::XYZ);
+    // Align model to origin
+    let bounds = model.model_dims();
+    model.mv(bounds.0 * Vec3::from_scalar(-1.0));
+    // Scale model to fit in 10x10x10 cube
+    let scale = model.scale_for_box(box_scale);
+    model.scale(Vec3::from_scalar(scale.min_val()));
+
+    // Convert to array
+    let arr = model_2_arr(model, dims, job.resolution);
+    let nodes = array_model_to_nodes(arr);
+    // The clusters start out in the bottom layer
+    if nodes.first().is_none_or(|layer| layer.is_empty()) {
+        return Err(TurtError::BadJob(format!("{} has no blocks in its bottom layer at size {}", job.model, job.size)));
     }
-    panic!()
+    let centroids = k_means(&nodes, dims, turtles);
+    Ok(centroids_to_groupings(nodes, centroids, dims))
 }
 
+async fn run_chunk_digger(data: DefaultData<'_>, start_pos: &PosH, job: &ChunkDiggerJob) -> Result<(), TurtError> {
+    let (p1, p2) = job.strip(start_pos, data.1);
+    let conf = ChunkDiggerConfig {
+        p1,
+        p2,
+        place_floor: job.place_floor.clone(),
+        chest_size: job.chest_size,
+        check_inv_every_n_blocks: job.check_inv_every_n_blocks,
+    };
+    let mut digger = ChunkDigger::init(data, conf)?;
+    digger.run().await
+}
 
-pub fn turtle_registered(identifier: TurtleIdentifier, channels_client: ChannelsClient) {
-    let turt = TurtControl::new(
-        channels_client.0.clone(),
-        &channels_client.1);
-    let mut nav = TurtNavigation::new(
-        identifier,
-        &turt,
-        true,
-        channels_client.0.clone(),
-        &channels_client.1);
-
-    let ind = id_to_i(identifier);
-
+async fn run_model_builder(data: DefaultData<'_>, start_pos: &PosH, job: &ModelBuilderJob, turtles: usize) -> Result<(), TurtError> {
+    let ind = data.1;
+    let mut model_builder = ModelBuilder::new(
+        data,
+        ModelBuilderConfig {
+            start_pos: start_pos.into(),
+            max_chests: job.max_chests,
+            chest_slots: job.chest_slots,
+            allowed_blocks: job.allowed_blocks.clone(),
+        })?;
+
+    // Clustering takes a while, keep it off the async workers
+    let model_job = job.clone();
+    let groupings = match task::spawn_blocking(move || get_model(&model_job, turtles)).await {
+        Ok(groupings) => groupings?,
+        Err(e) => return Err(TurtError::BadJob(format!("Couldn't split up model {}: {}", job.model, e))),
+    };
+    let groupings = groupings.get(ind).ok_or_else(|| {
+        TurtError::BadJob(format!("{} was only split into {} parts, not one per turtle", job.model, groupings.len()))
+    })?;
+    model_builder.run(&groupings.0, groupings.1).await
+}
 
-    nav.gps_init();
-    println!("Turtle {} registered! {}", identifier, nav);
+async fn run_job(identifier: TurtleIdentifier, turt: &TurtControl, jobid: JobId, job: JobConfig) -> Result<(), TurtError> {
+    let ind = match job.index_of(identifier) {
+        Some(ind) => ind,
+        None => {
+            turt.emit(TurtEventKind::Error { msg: format!("Not part of job {}", jobid) });
+            return Ok(());
+        }
+    };
 
-    let mut model_builder = ModelBuilder::new(
-        (
-            identifier,
-            ind,
-            &turt,
-            &mut nav
-        ), ModelBuilderConfig {
-            start_pos: Pos::new(START_POS.x, START_POS.y, START_POS.z),
-            max_chests: 7,
-            allowed_blocks: vec![
-                "minecraft:yellow_terracotta".to_string(),
-                "minecraft:pink_terracotta".to_string(),
-                "minecraft:orange_terracotta".to_string(),
-                "minecraft:light_blue_terracotta".to_string(),
-                "minecraft:lime_terracotta".to_string(),
-                "minecraft:blue_terracotta".to_string(),
-                "minecraft:white_terracotta".to_string(),
-                "minecraft:magenta_terracotta".to_string(),
-                "minecraft:terracotta".to_string(),
-                "minecraft:purple_terracotta".to_string(),
-                "minecraft:black_terracotta".to_string(),
-                "minecraft:green_terracotta".to_string(),
-                "minecraft:gray_terracotta".to_string(),
-                "minecraft:brown_terracotta".to_string(),
-                "minecraft:cyan_terracotta".to_string(),
-                "minecraft:red_terracotta".to_string(),
-                "minecraft:light_gray_terracotta".to_string(),
-            ],
-        });
+    turt.update_status(|s| {
+        s.job = Some(jobid);
+        s.progress = None;
+    });
 
-    let groupings = get_model(PATH);
-    let groupings = &groupings[ind];
-    model_builder.run(&groupings.0, groupings.1);
+    let mut nav = TurtNavigation::new(
+        identifier,
+        turt,
+        true);
+
+    // Before locating, so that the job's blocks to leave alone and fuel depot apply to its first moves
+    nav.set_fuel_config(job.fuel.clone());
+    nav.set_nav_config(job.navigation.clone());
+    nav.gps_init().await?;
+    turt.emit(TurtEventKind::JobStarted { job: jobid });
+
+    let data = (identifier, ind, turt, &mut nav);
+    match &job.kind {
+        JobKind::ChunkDigger(j) => run_chunk_digger(data, &job.start_pos, j).await?,
+        JobKind::ModelBuilder(j) => run_model_builder(data, &job.start_pos, j, job.turtles.len()).await?,
+    }
+    turt.update_status(|s| s.job = None);
+    turt.emit(TurtEventKind::JobFinished { job: jobid });
+    Ok(())
+}
 
-    turt.disconnect();
+/// Runs every job that is assigned to the turtle. Until then the turtle waits on `WAIT`.
+/// Returns once the turtle has registered again, so that the new worker can take over.
+pub async fn turtle_registered(identifier: TurtleIdentifier, channels_client: ChannelsClient) {
+    let ChannelsClient { stop, next_tx, cmdcomplete_rx, mut assign_rx, status, events, control, map, reservations, .. } =
+        channels_client;
+
+    // Give up the blocks the previous worker claimed but didn't get to move into
+    let pos = status.read().unwrap().pos.clone();
+    match pos {
+        Some(pos) => reservations.lock().unwrap().occupy(identifier, &(&pos).into()),
+        None => reservations.lock().unwrap().forget(identifier),
+    }
+    let turt = TurtControl::new(next_tx, cmdcomplete_rx, status, events, control, map, reservations)
+        .stop_when(stop);
+
+    // The channel closes when the turtle registers again
+    while let Some((jobid, job)) = assign_rx.recv().await {
+        match run_job(identifier, &turt, jobid, job).await {
+            Ok(()) => (),
+            Err(TurtError::Disconnected) => return,
+            // The job is abandoned, the turtle waits until it is assigned one again
+            Err(e) => {
+                turt.update_status(|s| s.job = None);
+                turt.emit(TurtEventKind::Error { msg: format!("Job {} failed: {}", jobid, e) });
+            }
+        }
+    }
 }
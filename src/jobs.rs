@@ -0,0 +1,151 @@
+//! Jobs describe what a group of turtles should be doing. They are either loaded from a TOML file
+//! that lives next to `Rocket.toml`, or created at runtime through the `/jobs` endpoints.
+use rocket::figment::Figment;
+use rocket::figment::providers::{Env, Format, Toml};
+use serde::{Deserialize, Serialize};
+use modelutils_rs::float;
+use modelutils_rs::model2arr::uint;
+use crate::turtle_core::fuel::FuelConfig;
+use crate::turtle_core::inventory::TurtBlock;
+use crate::turtle_core::navigation::{Pos, PosH};
+use crate::turtle_core::pathfinding::NavConfig;
+use crate::{TurtleIdentifier, TurtleIndex};
+
+pub const JOB_FILE: &str = "Jobs.toml";
+/// Environment variable that can be used to point at a different job file.
+pub const JOB_FILE_ENV: &str = "PROTU_JOB";
+
+pub type JobId = usize;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChunkDiggerJob {
+    /// Length of each turtle's strip along the z-axis.
+    pub depth: i64,
+    /// Width of each turtle's strip along the x-axis.
+    pub width: i64,
+    pub height: i64,
+    pub place_floor: TurtBlock,
+    pub chest_size: usize,
+    pub check_inv_every_n_blocks: usize,
+}
+
+impl ChunkDiggerJob {
+    /// Corners of the strip dug by the turtle at `index`.
+    pub fn strip(&self, start_pos: &PosH, index: TurtleIndex) -> (Pos, Pos) {
+        let ind = index as i64;
+        (
+            Pos::new(start_pos.x + ind * self.width, start_pos.y, start_pos.z - self.depth),
+            Pos::new(start_pos.x + (ind + 1) * self.width, start_pos.y + self.height, start_pos.z - self.depth),
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ModelBuilderJob {
+    /// Path to the `.obj` file.
+    pub model: String,
+    pub size: uint,
+    pub resolution: float,
+    /// Rotation around the y-axis in degrees.
+    #[serde(default)]
+    pub rotation: float,
+    pub max_chests: usize,
+    pub chest_slots: usize,
+    pub allowed_blocks: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobKind {
+    ChunkDigger(ChunkDiggerJob),
+    ModelBuilder(ModelBuilderJob),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct JobConfig {
+    pub turtles: Vec<TurtleIdentifier>,
+    pub start_pos: PosH,
+    /// Where the turtles refuel. Without it they keep going until they run out.
+    #[serde(default)]
+    pub fuel: Option<FuelConfig>,
+    #[serde(default)]
+    pub navigation: NavConfig,
+    #[serde(flatten)]
+    pub kind: JobKind,
+}
+
+impl JobConfig {
+    /// Loads the job file given by `PROTU_JOB`, or `Jobs.toml` if it is not set.
+    /// Returns `None` if there is no job file.
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        let path = Env::var_or(JOB_FILE_ENV, JOB_FILE);
+        if !std::path::Path::new(&path).exists() {
+            return Ok(None);
+        }
+        Ok(Some(Figment::from(Toml::file(path)).extract()?))
+    }
+
+    /// Corners of the area covered by the whole job.
+    pub fn bounds(&self) -> (Pos, Pos) {
+        let s = &self.start_pos;
+        match &self.kind {
+            JobKind::ChunkDigger(j) => {
+                let last = self.turtles.len().max(1) - 1;
+                (j.strip(s, 0).0, j.strip(s, last).1)
+            }
+            JobKind::ModelBuilder(j) => {
+                let size = j.size as i64 - 1;
+                (s.into(), Pos::new(s.x + size, s.y + size, s.z + size))
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match &self.kind {
+            JobKind::ChunkDigger(_) => "chunk_digger",
+            JobKind::ModelBuilder(_) => "model_builder",
+        }
+    }
+
+    /// Position of the turtle within the job, used to split up the work.
+    pub fn index_of(&self, turtleid: TurtleIdentifier) -> Option<TurtleIndex> {
+        self.turtles.iter().position(|t| *t == turtleid)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub conf: JobConfig,
+    pub started: bool,
+}
+
+impl Job {
+    pub fn new(conf: JobConfig) -> Self {
+        Self {
+            conf,
+            started: false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JobSummary {
+    pub id: JobId,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub started: bool,
+    pub turtles: Vec<TurtleIdentifier>,
+    pub bounds: (Pos, Pos),
+}
+
+impl JobSummary {
+    pub fn new(id: JobId, job: &Job) -> Self {
+        Self {
+            id,
+            kind: job.conf.name(),
+            started: job.started,
+            turtles: job.conf.turtles.clone(),
+            bounds: job.conf.bounds(),
+        }
+    }
+}
@@ -1,20 +1,44 @@
+use std::path::PathBuf;
+use std::sync::RwLock;
+
 pub mod turtle_core;
 pub mod server;
 pub mod scripts;
 pub mod entry;
+pub mod jobs;
+pub mod auth;
+#[cfg(feature = "sim")]
+pub mod sim;
 
 pub type TurtleIdentifier = usize;
 pub type TurtleIndex = usize;
 pub type DefaultData<'a> = (
     TurtleIdentifier,
     TurtleIndex,
-    &'a turtle_core::control::TurtControl<'a>,
+    &'a turtle_core::control::TurtControl,
     &'a mut turtle_core::navigation::TurtNavigation<'a>,
 );
 
 pub const PROGRESS_DIR: &str = "progress";
 
+/// Where `NAV_DIR`, `PROGRESS_DIR` and `MAP_DIR` go. The working directory unless set.
+static DATA_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Saves positions, progress and the world map in `dir` instead of the working directory.
+pub fn set_data_dir(dir: impl Into<PathBuf>) {
+    *DATA_DIR.write().unwrap() = Some(dir.into());
+}
+
+/// `dir`, one of `NAV_DIR`, `PROGRESS_DIR` or `MAP_DIR`, in the data directory.
+pub fn data_path(dir: &str) -> PathBuf {
+    match &*DATA_DIR.read().unwrap() {
+        Some(data_dir) => data_dir.join(dir),
+        None => PathBuf::from(dir),
+    }
+}
+
 pub fn init_dirs() {
-    std::fs::create_dir_all(turtle_core::navigation::NAV_DIR).unwrap();
-    std::fs::create_dir_all(PROGRESS_DIR).unwrap();
+    std::fs::create_dir_all(data_path(turtle_core::navigation::NAV_DIR)).unwrap();
+    std::fs::create_dir_all(data_path(PROGRESS_DIR)).unwrap();
+    std::fs::create_dir_all(data_path(turtle_core::world_map::MAP_DIR)).unwrap();
 }
@@ -1,12 +1,14 @@
-use crate::{DefaultData, PROGRESS_DIR, TurtleIdentifier};
-use crate::turtle_core::control::TurtControl;
+use crate::{data_path, DefaultData, PROGRESS_DIR, TurtleIdentifier};
+use crate::turtle_core::control::{TurtControl, TurtDir};
+use crate::turtle_core::error::TurtError;
 use crate::turtle_core::navigation::{Head, Pos, PosH, TurtNavigation};
 use crate::turtle_core::inventory::{TurtInventory, TURT_SLOTS, TurtBlock};
 use modelutils_rs::coords::Order;
+use rocket::tokio::time::{self, Duration};
 
-use std::path;
 use std::path::PathBuf;
 use crate::turtle_core::file_system_storage::{FStore, fstore_load_or_init, fstore_save};
+use crate::turtle_core::status::JobProgress;
 
 
 #[derive(Debug, Clone)]
@@ -42,13 +44,13 @@ impl FStore for FStoreChunkDigger {
         format!("{}\n{}\n", self.layer, self.stack_count)
     }
 
-    fn load(p: &PathBuf, d: &str) -> Self {
+    fn load(p: &PathBuf, d: &str) -> Option<Self> {
         let lines: Vec<String> = d.lines().map(String::from).collect();
-        Self {
+        Some(Self {
             fp: p.clone(),
-            layer: lines[0].parse::<usize>().unwrap(),
-            stack_count: lines[1].parse::<usize>().unwrap(),
-        }
+            layer: lines.first()?.parse::<usize>().ok()?,
+            stack_count: lines.get(1)?.parse::<usize>().ok()?,
+        })
     }
 }
 
@@ -56,7 +58,7 @@ impl FStore for FStoreChunkDigger {
 pub struct ChunkDigger<'a> {
     _identifier: TurtleIdentifier,
     _index: usize,
-    turt: &'a TurtControl<'a>,
+    turt: &'a TurtControl,
     nav: &'a mut TurtNavigation<'a>,
     inv: TurtInventory<'a>,
 
@@ -65,11 +67,10 @@ pub struct ChunkDigger<'a> {
 }
 
 impl<'a> ChunkDigger<'a> {
-    pub fn init(data: DefaultData<'a>, conf: ChunkDiggerConfig) -> Self {
-        let fp = path::PathBuf::from(
-            format!("{}/{}.chunkdigger", PROGRESS_DIR, data.0));
-        let fstore_chunk_digger = fstore_load_or_init::<FStoreChunkDigger>(&fp);
-        Self {
+    pub fn init(data: DefaultData<'a>, conf: ChunkDiggerConfig) -> Result<Self, TurtError> {
+        let fp = data_path(PROGRESS_DIR).join(format!("{}.chunkdigger", data.0));
+        let fstore_chunk_digger = fstore_load_or_init::<FStoreChunkDigger>(&fp)?;
+        Ok(Self {
             _identifier: data.0,
             _index: data.1,
             turt: data.2,
@@ -77,11 +78,11 @@ impl<'a> ChunkDigger<'a> {
             inv: TurtInventory::init(&data.2),
             conf,
             fstore_chunk_digger,
-        }
+        })
     }
 
-    fn inv_check(&mut self) {
-        self.inv.full_update();
+    async fn inv_check(&mut self) -> Result<(), TurtError> {
+        self.inv.full_update().await?;
         if self.inv.is_full() {
             let chest_size = self.conf.chest_size;
 
@@ -97,30 +98,31 @@ impl<'a> ChunkDigger<'a> {
             // Calculate first chest location and go there
             let mut chest_loc: PosH = self.conf.p1.clone().into();
             chest_loc.z = self.conf.p2.z - offset as i64;
-            self.nav.goto_head(&chest_loc, Order::XYZ);
+            self.nav.goto_head(&chest_loc, Order::XYZ).await?;
 
             // Place items in chest
             for s in 0..max_chest_space.min(TURT_SLOTS) as usize {
-                self.turt.inv_select(s as u8);
-                self.turt.inv_drop_down();
+                self.turt.inv_select(s as u8).await?;
+                self.turt.inv_drop_down().await?;
             }
             // Check if chest didn't have enough slots for all items
             if max_chest_space < TURT_SLOTS {
                 // Repeat
                 chest_loc.z -= 1;
-                self.nav.goto_head(&chest_loc, Order::XYZ);
+                self.nav.goto_head(&chest_loc, Order::XYZ).await?;
 
                 for s in max_chest_space..TURT_SLOTS {
-                    self.turt.inv_select(s as u8);
-                    self.turt.inv_drop_down();
+                    self.turt.inv_select(s as u8).await?;
+                    self.turt.inv_drop_down().await?;
                 }
             };
-            fstore_save(&self.fstore_chunk_digger);
-            self.inv.full_update();
+            self.save_progress()?;
+            self.inv.full_update().await?;
 
             // Return to mining position
-            self.nav.goto_head(&saved_pos, Order::XYZ);
+            self.nav.goto_head(&saved_pos, Order::XYZ).await?;
         }
+        Ok(())
     }
 
     /// All of p1's values are lower than p2.
@@ -150,21 +152,32 @@ impl<'a> ChunkDigger<'a> {
         }
     }
 
-    pub fn save_progress(&self) {
-        fstore_save(&self.fstore_chunk_digger)
+    fn layers(&self) -> usize {
+        (self.conf.p1.y.abs_diff(self.conf.p2.y) / 3) as usize
+    }
+
+    pub fn save_progress(&self) -> Result<(), TurtError> {
+        fstore_save(&self.fstore_chunk_digger)?;
+        self.turt.update_status(|s| s.progress = Some(JobProgress::ChunkDigger {
+            layer: self.fstore_chunk_digger.layer,
+            layers: self.layers(),
+            stack_count: self.fstore_chunk_digger.stack_count,
+        }));
+        Ok(())
     }
 
     /// All of p1's values are lower or equal to those of p2.
-    pub fn run(&mut self) {
+    pub async fn run(&mut self) -> Result<(), TurtError> {
         let p1 = self.conf.p1.clone();
         let p2 = self.conf.p2.clone();
         let mut p = PosH::default();
 
-        let y_diff = (p1.y.abs_diff(p2.y) / 3) as usize;
+        let y_diff = self.layers();
         let x_diff = p1.x.abs_diff(p2.x) as usize + 1;
         let z_diff = p1.z.abs_diff(p2.z) as usize + 1;
 
         let mut curr_slot = 0;
+        let never_dig = self.nav.never_dig();
 
         for y in self.fstore_chunk_digger.layer..y_diff {
             p.y = p1.y + (y as i64 * 3) + 1;
@@ -193,45 +206,49 @@ impl<'a> ChunkDigger<'a> {
                         _ => panic!(),
                     }
 
-                    self.nav.goto_head(&p, Order::XYZ);
+                    self.nav.goto_head(&p, Order::XYZ).await?;
 
-                    self.turt.dig_up().unwrap();
-                    self.turt.dig_down().unwrap();
+                    // Dig, leaving blocks that mustn't be dug, and place in one round-trip
+                    let mut batch = self.turt
+                        .batch()
+                        .dig_unless(TurtDir::Up, &never_dig)
+                        .dig_unless(TurtDir::Down, &never_dig);
 
                     match &self.conf.place_floor {
                         TurtBlock::None => (),
                         TurtBlock::Any => loop {
                             // Select non-empty slot and place
-                            let slot = self.inv.reduce_count_andor_find_next(curr_slot);
+                            let slot = self.inv.reduce_count_andor_find_next(curr_slot).await?;
                             if let Some(s) = slot {
                                 let s = s as usize;
                                 if curr_slot != s {
                                     curr_slot = s;
-                                    self.turt.inv_select(curr_slot as u8);
+                                    batch = batch.inv_select(curr_slot as u8);
                                 }
-                                self.turt.place_down();
+                                batch = batch.place_down();
                                 break;
                             } else {
-                                self.turt.print("Out of blocks! Please add more.");
-                                std::thread::sleep(std::time::Duration::from_millis(1000));
+                                self.turt.print("Out of blocks! Please add more.").await?;
+                                time::sleep(Duration::from_millis(1000)).await;
                             }
                         }
                         TurtBlock::Some(_block) => {
                             unimplemented!("ChunkDigger::run: TurtBlock::Some(_block)")
                         }
                     }
+                    batch.run().await?;
 
                     if z % self.conf.check_inv_every_n_blocks == 0 {
-                        self.inv_check();
+                        self.inv_check().await?;
                     }
                 }
-                self.inv_check();
+                self.inv_check().await?;
             }
             self.fstore_chunk_digger.layer += 1;
-            self.save_progress();
+            self.save_progress()?;
         }
         let mut chest_loc: PosH = p1.clone().into();
         chest_loc.z = self.conf.p2.z;
-        self.nav.goto_head(&chest_loc, Order::XYZ);
+        self.nav.goto_head(&chest_loc, Order::XYZ).await
     }
 }
@@ -1,13 +1,16 @@
 use std::path::PathBuf;
 use modelutils_rs::coords::Order;
 use modelutils_rs::model2arr::{Block, CoordXZ, uint};
-use crate::{DefaultData, PROGRESS_DIR, TurtleIdentifier};
+use rocket::tokio::time::{self, Duration};
+use crate::{data_path, DefaultData, PROGRESS_DIR, TurtleIdentifier};
 use crate::scripts::model_builder::generation::{join_paths_greedily, mst_to_paths, nodes_to_mst};
-use crate::turtle_core::control::TurtControl;
-use crate::turtle_core::data::TurtResponse;
+use crate::turtle_core::control::{TurtControl, TurtDir};
+use crate::turtle_core::error::TurtError;
+use crate::turtle_core::events::TurtEventKind;
 use crate::turtle_core::file_system_storage::{FStore, fstore_load_or_init, fstore_save};
 use crate::turtle_core::inventory::{TURT_SLOTS, TurtInventory};
 use crate::turtle_core::navigation::{Pos, PosH, TurtNavigation};
+use crate::turtle_core::status::JobProgress;
 
 #[derive(Debug)]
 struct FStoreModelBuilder {
@@ -31,12 +34,12 @@ impl FStore for FStoreModelBuilder {
         format!("{}\n", self.start_layer)
     }
 
-    fn load(p: &PathBuf, d: &str) -> Self {
+    fn load(p: &PathBuf, d: &str) -> Option<Self> {
         let lines: Vec<String> = d.lines().map(String::from).collect();
-        Self {
+        Some(Self {
             fp: p.clone(),
-            start_layer: lines[0].parse::<usize>().unwrap(),
-        }
+            start_layer: lines.first()?.parse::<usize>().ok()?,
+        })
     }
 }
 
@@ -44,14 +47,15 @@ impl FStore for FStoreModelBuilder {
 pub struct ModelBuilderConfig {
     pub start_pos: Pos,
     pub max_chests: usize,
+    pub chest_slots: usize,
     pub allowed_blocks: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct ModelBuilder<'a> {
-    identifier: TurtleIdentifier,
+    _identifier: TurtleIdentifier,
     index: usize,
-    turt: &'a TurtControl<'a>,
+    turt: &'a TurtControl,
     nav: &'a mut TurtNavigation<'a>,
     inv: TurtInventory<'a>,
 
@@ -62,45 +66,44 @@ pub struct ModelBuilder<'a> {
 impl<'a> ModelBuilder<'a> {
     pub fn new(
         data: DefaultData<'a>, conf: ModelBuilderConfig,
-    ) -> Self {
-        let fp = PathBuf::from(
-            format!("{}/{}.modelbuilder", PROGRESS_DIR, data.0));
-        let fstore_model_builder = fstore_load_or_init::<FStoreModelBuilder>(&fp);
-        Self {
-            identifier: data.0,
+    ) -> Result<Self, TurtError> {
+        let fp = data_path(PROGRESS_DIR).join(format!("{}.modelbuilder", data.0));
+        let fstore_model_builder = fstore_load_or_init::<FStoreModelBuilder>(&fp)?;
+        Ok(Self {
+            _identifier: data.0,
             index: data.1,
             turt: data.2,
             nav: data.3,
             inv: TurtInventory::init(&data.2),
             conf,
             fstore_model_builder,
-        }
+        })
     }
 
-    pub fn save_progress(&self) {
-        fstore_save(&self.fstore_model_builder)
+    pub fn save_progress(&self) -> Result<(), TurtError> {
+        Ok(fstore_save(&self.fstore_model_builder)?)
     }
 
-    fn clear_inv(&mut self) {
+    async fn clear_inv(&mut self) -> Result<(), TurtError> {
         for s in 0..TURT_SLOTS {
-            if let Some(block) = self.turt.inv_item_detail(s as u8) {
+            if let Some(block) = self.turt.inv_item_detail(s as u8).await? {
                 if !self.conf.allowed_blocks.contains(&block.name().to_string()) {
-                    self.turt.inv_select(s as u8);
-                    self.turt.inv_drop_forw();
+                    self.turt.inv_select(s as u8).await?;
+                    self.turt.inv_drop_forw().await?;
                 }
             }
         }
-        self.inv.full_update();
+        self.inv.full_update().await
     }
 
-    pub fn inv_update(&mut self, curr_slot: &mut u8) {
-        if let Some(next_slot) = self.inv.reduce_count_andor_find_next(*curr_slot as usize) {
+    pub async fn inv_update(&mut self, curr_slot: &mut u8) -> Result<(), TurtError> {
+        if let Some(next_slot) = self.inv.reduce_count_andor_find_next(*curr_slot as usize).await? {
             *curr_slot = next_slot as u8;
-            self.turt.inv_select(*curr_slot);
-            return;
+            self.turt.inv_select(*curr_slot).await?;
+            return Ok(());
         }
-        println!("Inventory empty! Refilling... [{}]", self.identifier);
-        self.clear_inv();
+        self.turt.emit(TurtEventKind::InventoryRefill);
+        self.clear_inv().await?;
 
         // Save position of turtle (to return to)
         let saved_pos = self.nav.pos().clone();
@@ -112,45 +115,38 @@ impl<'a> ModelBuilder<'a> {
         // Go to lowest y-level first and then to chest
         let mut next_pos = saved_pos.clone();
         next_pos.y = self.conf.start_pos.y;
-        self.nav.goto_head(&next_pos, Order::XYZ);
+        self.nav.goto_head(&next_pos, Order::XYZ).await?;
         next_pos.x = chest_loc.x;
-        self.nav.goto_head(&next_pos, Order::XYZ);
-        self.nav.goto_head(&chest_loc, Order::XYZ);
+        self.nav.goto_head(&next_pos, Order::XYZ).await?;
+        self.nav.goto_head(&chest_loc, Order::XYZ).await?;
 
         // Refill inventory
         let mut first = true;
         while !self.inv.is_full() {
             if !first {
-                println!("Waiting for chest to refill... [{}]", self.identifier);
-                std::thread::sleep(std::time::Duration::from_millis(10000));
+                self.turt.emit(TurtEventKind::WaitingForChest);
+                time::sleep(Duration::from_millis(10000)).await;
             }
             first = false;
             let mut offset = 0;
             for _s in 0..TURT_SLOTS {
-                let r = self.turt.suck_down();
-                let success = match r {
-                    TurtResponse::Ok(r) => {
-                        r.as_array().unwrap()[0].as_bool().unwrap()
-                    }
-                    _ => false
-                };
-                if !success {
+                if !self.turt.suck_down().await?.success() {
                     offset += 1;
                     offset %= self.conf.max_chests;
                     chest_loc.z = self.conf.start_pos.z - offset as i64;
-                    self.nav.goto_head(&chest_loc, Order::XYZ);
+                    self.nav.goto_head(&chest_loc, Order::XYZ).await?;
                     continue;
                 }
-                self.clear_inv();
+                self.clear_inv().await?;
             }
         }
 
         // Go underneath building point
         next_pos.x = saved_pos.x;
-        self.nav.goto_head(&next_pos, Order::XYZ);
+        self.nav.goto_head(&next_pos, Order::XYZ).await?;
 
         // Return to mining position
-        self.nav.goto_head(&saved_pos, Order::XYZ);
+        self.nav.goto_head(&saved_pos, Order::XYZ).await
     }
 
 
@@ -159,9 +155,9 @@ impl<'a> ModelBuilder<'a> {
         (p.x as uint, p.z as uint)
     }
 
-    pub fn run(&mut self, nodes: &Vec<Vec<(CoordXZ, Block)>>, count: usize) {
+    pub async fn run(&mut self, nodes: &Vec<Vec<(CoordXZ, Block)>>, count: usize) -> Result<(), TurtError> {
         let num_chests = (count as f32 / 64.0 / self.conf.chest_slots as f32).ceil() as usize;
-        let mut need_more_chests = match self.turt.inv_item_detail(0) {
+        let mut need_more_chests = match self.turt.inv_item_detail(0).await? {
             Some(chests) => {
                 if chests.count() < num_chests as i32 {
                     true
@@ -179,9 +175,9 @@ impl<'a> ModelBuilder<'a> {
         }
 
         if need_more_chests {
-            println!("Not enough chests! Need at least: {}", num_chests);
-            std::thread::sleep(std::time::Duration::from_millis(10000));
-            return;
+            self.turt.emit(TurtEventKind::Error { msg: format!("Not enough chests! Need at least: {}", num_chests) });
+            time::sleep(Duration::from_millis(10000)).await;
+            return Ok(());
         }
 
         for i in 0..num_chests {
@@ -189,25 +185,25 @@ impl<'a> ModelBuilder<'a> {
                 self.conf.start_pos.x + self.index as i64,
                 self.conf.start_pos.y,
                 self.conf.start_pos.z - i as i64,
-            ), Order::XYZ);
-            self.inv.full_update();
+            ), Order::XYZ).await?;
+            self.inv.full_update().await?;
             match self.inv.slots[0] {
                 Some(ref mut chest) => {
                     if chest.name() != "minecraft:chest" {
-                        println!("Slot 0 is not a chest!");
-                        std::thread::sleep(std::time::Duration::from_millis(10000));
-                        return;
+                        self.turt.emit(TurtEventKind::Error { msg: "Slot 0 is not a chest!".to_string() });
+                        time::sleep(Duration::from_millis(10000)).await;
+                        return Ok(());
                     }
                 }
                 None => {
-                    println!("No chest in slot 0!");
-                    std::thread::sleep(std::time::Duration::from_millis(10000));
-                    return;
+                    self.turt.emit(TurtEventKind::Error { msg: "No chest in slot 0!".to_string() });
+                    time::sleep(Duration::from_millis(10000)).await;
+                    return Ok(());
                 }
             }
-            self.turt.inv_select(0);
-            self.turt.dig_down();
-            self.turt.place_down();
+            self.turt.inv_select(0).await?;
+            self.turt.dig_unless(TurtDir::Down, &self.nav.never_dig()).await?;
+            self.turt.place_down().await?;
         }
 
         fn world_coord(start: &Pos, coord: CoordXZ, y: usize) -> Pos {
@@ -226,20 +222,25 @@ impl<'a> ModelBuilder<'a> {
             if layer.is_empty() { continue; }
 
             self.fstore_model_builder.start_layer = y;
-            self.save_progress();
+            self.save_progress()?;
+            self.turt.update_status(|s| s.progress = Some(JobProgress::ModelBuilder {
+                layer: y,
+                layers: nodes.len(),
+            }));
 
             let mst = nodes_to_mst(&layer);
             let paths = mst_to_paths(mst);
             let path = join_paths_greedily(self.curr_xz(), paths, &layer);
 
             for node in path {
-                self.inv_update(&mut curr_slot);
+                self.inv_update(&mut curr_slot).await?;
 
                 let (coord, _block) = layer[node as usize];
-                self.nav.goto_nohead(&world_coord(&self.conf.start_pos, coord, rev_y), Order::XYZ);
+                self.nav.goto_nohead(&world_coord(&self.conf.start_pos, coord, rev_y), Order::XYZ).await?;
 
-                self.turt.place_up();
+                self.turt.place_up().await?;
             }
         }
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,48 +1,155 @@
+use crate::turtle_core::command::TurtRequest;
+use crate::turtle_core::control::{TurtControlConfig, TurtReply};
 use crate::turtle_core::data::{TurtRawResponse, TurtResponse};
+use crate::turtle_core::events::{EventBus, TurtEvent, TurtEventKind};
+use crate::turtle_core::status::{SharedStatus, TurtStatus};
+use crate::turtle_core::reservations::{Reservations, SharedReservations};
+use crate::turtle_core::world_map::{MapEntry, SharedWorldMap, WorldMap, MAP_DIR};
+use crate::turtle_core::navigation::Pos;
 
+use rocket::fairing::AdHoc;
+use rocket::fs::{relative, FileServer, Options};
+use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
-use rocket::{get, post, routes, State};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::sync::{mpsc, Mutex};
+use rocket::tokio::task::JoinHandle;
+use rocket::tokio::time::{self, Duration};
+use rocket::http::Status;
+use rocket::figment::Figment;
+use rocket::{get, post, routes, Build, Rocket, Shutdown, State};
 
-use std::thread;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
-use std::sync::{mpsc, RwLock};
-use crate::entry;
+use std::sync::{Arc, RwLock};
+use crate::auth::{AuthConfig, OperatorAuth, TurtleAuth};
+use crate::{data_path, entry};
+use crate::jobs::{Job, JobConfig, JobId, JobSummary};
+use crate::TurtleIdentifier;
 
 const TURTLE_CAPACITY: usize = 32;
+const STATUS_STREAM_INTERVAL: Duration = Duration::from_millis(1000);
+const MAP_SAVE_INTERVAL: Duration = Duration::from_millis(10_000);
 
-pub type ChannelsClient = (mpsc::Sender<String>, mpsc::Receiver<TurtResponse>);
+/// Settings read from `Rocket.toml`, next to Rocket's own.
+#[derive(serde::Deserialize)]
+struct ServerConfig {
+    /// How long `/next` holds a request open waiting for a command before answering `WAIT`.
+    #[serde(default = "ServerConfig::default_next_timeout_ms")]
+    next_timeout_ms: u64,
+    #[serde(default)]
+    turtle_control: TurtControlConfig,
+}
 
-struct ChannelsServer {
-    next_rx: mpsc::Receiver<String>,
-    cmdcomplete_tx: mpsc::Sender<TurtResponse>,
+impl ServerConfig {
+    fn default_next_timeout_ms() -> u64 {
+        10_000
+    }
+
+    fn next_timeout(&self) -> Duration {
+        Duration::from_millis(self.next_timeout_ms)
+    }
 }
 
-unsafe impl Sync for ChannelsServer {}
+/// Identifies one registration of a turtle. A turtle gets a new session every time it registers.
+pub type SessionId = u64;
+
+/// The worker's end of a turtle's channels.
+///
+/// When the turtle registers again, `stop` is set and the server's end is dropped. The worker
+/// notices this the next time it talks to the turtle (see `TurtControl::make_req`) or waits for a
+/// job, and stops.
+pub struct ChannelsClient {
+    pub session: SessionId,
+    pub stop: Arc<AtomicBool>,
+    pub next_tx: mpsc::UnboundedSender<TurtRequest>,
+    pub cmdcomplete_rx: mpsc::UnboundedReceiver<TurtReply>,
+    /// Receives the jobs that are handed out to the turtle.
+    pub assign_rx: mpsc::UnboundedReceiver<(JobId, JobConfig)>,
+    pub status: SharedStatus,
+    pub events: EventBus,
+    pub control: TurtControlConfig,
+    pub map: SharedWorldMap,
+    pub reservations: SharedReservations,
+}
+
+struct ChannelsServer {
+    /// Behind an async mutex so that `/next` can wait on it without holding the `BotNet` lock.
+    next_rx: Arc<Mutex<mpsc::UnboundedReceiver<TurtRequest>>>,
+    cmdcomplete_tx: mpsc::UnboundedSender<TurtReply>,
+    assign_tx: mpsc::UnboundedSender<(JobId, JobConfig)>,
+    session: SessionId,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
 
 impl ChannelsServer {
-    fn new() -> (Self, ChannelsClient) {
-        let (next_tx, next_rx): (mpsc::Sender<String>, mpsc::Receiver<String>) = mpsc::channel();
-        let (cmdcomplete_tx, cmdcomplete_rx): (mpsc::Sender<TurtResponse>, mpsc::Receiver<TurtResponse>) =
-            mpsc::channel();
+    fn new(
+        session: SessionId,
+        status: SharedStatus,
+        events: EventBus,
+        control: TurtControlConfig,
+        map: SharedWorldMap,
+        reservations: SharedReservations,
+    ) -> (Self, ChannelsClient) {
+        let (next_tx, next_rx) = mpsc::unbounded_channel::<TurtRequest>();
+        let (cmdcomplete_tx, cmdcomplete_rx) = mpsc::unbounded_channel::<TurtReply>();
+        let (assign_tx, assign_rx) = mpsc::unbounded_channel::<(JobId, JobConfig)>();
+        let stop = Arc::new(AtomicBool::new(false));
 
         (
             Self {
-                next_rx,
+                next_rx: Arc::new(Mutex::new(next_rx)),
                 cmdcomplete_tx,
+                assign_tx,
+                session,
+                stop: stop.clone(),
+                worker: None,
+            },
+            ChannelsClient {
+                session,
+                stop,
+                next_tx,
+                cmdcomplete_rx,
+                assign_rx,
+                status,
+                events,
+                control,
+                map,
+                reservations,
             },
-            (next_tx, cmdcomplete_rx),
         )
     }
 }
 
 impl Default for ChannelsServer {
     fn default() -> Self {
-        Self::new().0
+        Self::new(
+            0,
+            TurtStatus::shared(0),
+            EventBus::new(),
+            TurtControlConfig::default(),
+            WorldMap::new().shared(),
+            Reservations::new().shared(),
+        ).0
     }
 }
 
 struct BotNet {
     turtles: RwLock<Vec<ChannelsServer>>,
+    jobs: RwLock<Vec<Job>>,
+    /// Job that each turtle is attached to.
+    assignments: RwLock<HashMap<TurtleIdentifier, JobId>>,
+    /// Status of every turtle that has registered at least once.
+    statuses: RwLock<HashMap<TurtleIdentifier, SharedStatus>>,
+    events: EventBus,
+    /// What the turtles have seen of the world, saved to `MAP_DIR`.
+    map: SharedWorldMap,
+    /// Blocks the turtles are in or about to move into.
+    reservations: SharedReservations,
+    last_session: AtomicU64,
 }
 
 impl BotNet {
@@ -53,60 +160,444 @@ impl BotNet {
         }
         Self {
             turtles: RwLock::new(turtles),
+            jobs: RwLock::new(vec![]),
+            assignments: RwLock::new(HashMap::new()),
+            statuses: RwLock::new(HashMap::new()),
+            events: EventBus::new(),
+            map: WorldMap::load(&data_path(MAP_DIR)).shared(),
+            reservations: Reservations::new().shared(),
+            last_session: AtomicU64::new(0),
         }
     }
 
-    fn register_turtle(&self, turtleid: usize) -> ChannelsClient {
+    /// Starts a new session for the turtle, replacing the previous one, along with a worker that
+    /// drives the turtle once the previous worker has finished. Returns the session ID.
+    fn register_turtle(&self, turtleid: usize, control: TurtControlConfig) -> SessionId {
+        let session = self.last_session.fetch_add(1, Ordering::Relaxed) + 1;
+        let status = self.statuses
+            .write()
+            .unwrap()
+            .entry(turtleid)
+            .or_insert_with(|| TurtStatus::shared(turtleid))
+            .clone();
+
         let mut turts = self.turtles.write().unwrap();
-        if turtleid > turts.len() {
+        if turtleid >= turts.len() {
             for _ in turts.len()..turtleid + 1 {
                 turts.push(ChannelsServer::default());
             }
         }
-        let (web_server_channels, client_channels) = ChannelsServer::new();
-        turts[turtleid] = web_server_channels;
-        client_channels
+        let (web_server_channels, client_channels) = ChannelsServer::new(
+            session,
+            status.clone(),
+            self.events.clone(),
+            control,
+            self.map.clone(),
+            self.reservations.clone(),
+        );
+        let mut previous = std::mem::replace(&mut turts[turtleid], web_server_channels);
+        previous.stop.store(true, Ordering::Relaxed);
+        // Only one worker may drive a turtle at a time, so that they don't fight over its position
+        // and progress files. The previous one stops before its next command, or at the one it is
+        // waiting on, which leaves its position file in step with the turtle. The worker is stored
+        // before the lock is released, so that the next registration is sure to wait for it.
+        let previous_worker = previous.worker.take();
+        turts[turtleid].worker = Some(rocket::tokio::spawn(async move {
+            if let Some(previous_worker) = previous_worker {
+                let _ = previous_worker.await;
+            }
+            entry::turtle_registered(turtleid, client_channels).await
+        }));
+        drop(turts);
+        status.write().unwrap().session = Some(session);
+        self.events.emit(TurtEvent::new(&status.read().unwrap(), TurtEventKind::Registered));
+
+        // Pick the job back up if the turtle was already working on one
+        let jobid = self.assignments.read().unwrap().get(&turtleid).copied();
+        if let Some(jobid) = jobid {
+            self.send_job(turtleid, jobid);
+        }
+        session
+    }
+
+    /// Whether `session` is the turtle's current session. Requests without a session are treated like
+    /// ones from an old session, so a turtle that doesn't send one has to register again.
+    fn check_session(&self, turtleid: TurtleIdentifier, session: Option<SessionId>) -> Result<(), Status> {
+        let session = session.ok_or(Status::Conflict)?;
+        match self.turtles.read().unwrap().get(turtleid) {
+            Some(turt) if turt.session == session => Ok(()),
+            _ => Err(Status::Conflict),
+        }
+    }
+
+    fn add_job(&self, conf: JobConfig) -> JobId {
+        let mut jobs = self.jobs.write().unwrap();
+        let mut assignments = self.assignments.write().unwrap();
+        let jobid = jobs.len();
+        for turtleid in conf.turtles.iter() {
+            if let Some(previous) = assignments.insert(*turtleid, jobid) {
+                leave_job(&mut jobs, previous, *turtleid);
+            }
+        }
+        jobs.push(Job::new(conf));
+        jobid
+    }
+
+    /// Hands the job out to every turtle attached to it. Does nothing if it was started already.
+    fn start_job(&self, jobid: JobId) -> Option<()> {
+        let turtles = {
+            let mut jobs = self.jobs.write().unwrap();
+            let job = jobs.get_mut(jobid)?;
+            if job.started {
+                return Some(());
+            }
+            job.started = true;
+            job.conf.turtles.clone()
+        };
+        for turtleid in turtles {
+            self.send_job(turtleid, jobid);
+        }
+        Some(())
+    }
+
+    /// Attaches a turtle to a job that hasn't started yet, taking it off the job it was attached to.
+    /// A job that has started can't take on more turtles, since the work is already split up between
+    /// the ones it has.
+    fn assign_turtle(&self, turtleid: TurtleIdentifier, jobid: JobId) -> Result<(), Status> {
+        let mut jobs = self.jobs.write().unwrap();
+        let job = jobs.get_mut(jobid).ok_or(Status::NotFound)?;
+        if job.conf.turtles.contains(&turtleid) {
+            return Ok(());
+        }
+        if job.started {
+            return Err(Status::Conflict);
+        }
+        job.conf.turtles.push(turtleid);
+        if let Some(previous) = self.assignments.write().unwrap().insert(turtleid, jobid) {
+            leave_job(&mut jobs, previous, turtleid);
+        }
+        Ok(())
+    }
+
+    fn status(&self, turtleid: TurtleIdentifier) -> Option<TurtStatus> {
+        self.statuses
+            .read()
+            .unwrap()
+            .get(&turtleid)
+            .map(|s| s.read().unwrap().clone())
+    }
+
+    fn all_statuses(&self) -> Vec<TurtStatus> {
+        let mut statuses: Vec<TurtStatus> = self.statuses
+            .read()
+            .unwrap()
+            .values()
+            .map(|s| s.read().unwrap().clone())
+            .collect();
+        statuses.sort_by_key(|s| s.id);
+        statuses
+    }
+
+    fn emit(&self, turtleid: TurtleIdentifier, kind: TurtEventKind) {
+        let event = match self.statuses.read().unwrap().get(&turtleid) {
+            Some(status) => TurtEvent::new(&status.read().unwrap(), kind),
+            None => TurtEvent::new(&TurtStatus::new(turtleid), kind),
+        };
+        self.events.emit(event);
+    }
+
+    fn job_summaries(&self) -> Vec<JobSummary> {
+        self.jobs
+            .read()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(id, job)| JobSummary::new(id, job))
+            .collect()
+    }
+
+    fn send_job(&self, turtleid: TurtleIdentifier, jobid: JobId) {
+        let job = match self.jobs.read().unwrap().get(jobid) {
+            Some(job) if job.started => job.conf.clone(),
+            _ => return,
+        };
+        match self.turtles.read().unwrap().get(turtleid) {
+            Some(turt) => match turt.assign_tx.send((jobid, job)) {
+                Ok(_) => println!("Turtle {turtleid} was assigned job {jobid}!"),
+                Err(_) => println!("Turtle {turtleid} will start job {jobid} once it registers."),
+            },
+            None => println!("Turtle {turtleid} will start job {jobid} once it registers."),
+        }
+    }
+}
+
+/// Takes the turtle off a job it was attached to. A job that has started keeps its list of turtles,
+/// so that the others' share of the work stays the same, and the turtle's share is left undone.
+fn leave_job(jobs: &mut [Job], jobid: JobId, turtleid: TurtleIdentifier) {
+    if let Some(job) = jobs.get_mut(jobid).filter(|j| !j.started) {
+        job.conf.turtles.retain(|t| *t != turtleid);
     }
 }
 
+/// Returns the session ID, which the turtle sends along with every following request.
 #[post("/<turtleid>")]
-async fn register(bot_net: &State<BotNet>, turtleid: usize) {
-    println!("Turtle {turtleid} is trying to register!");
-    let channels_client = bot_net.register_turtle(turtleid);
-    thread::spawn(move || {
-        entry::turtle_registered(turtleid.into(), channels_client)
-    });
+async fn register(
+    bot_net: &State<BotNet>,
+    config: &State<ServerConfig>,
+    _auth: TurtleAuth,
+    turtleid: usize,
+) -> String {
+    bot_net.register_turtle(turtleid, config.turtle_control.clone()).to_string()
 }
 
-#[get("/<turtleid>")]
-async fn next(bot_net: &State<BotNet>, turtleid: usize) -> String {
-    match bot_net.turtles.read().unwrap()[turtleid].next_rx.try_recv() {
-        Ok(v) => v,
-        Err(_) => "WAIT".to_string(),
+/// Holds the request open until there is a command for the turtle, or answers `WAIT` once
+/// `next_timeout_ms` has passed.
+#[get("/<turtleid>?<session>")]
+async fn next(
+    bot_net: &State<BotNet>,
+    config: &State<ServerConfig>,
+    _auth: TurtleAuth,
+    turtleid: usize,
+    session: Option<SessionId>,
+) -> Result<String, Status> {
+    bot_net.check_session(turtleid, session)?;
+    let next_rx = match bot_net.turtles.read().unwrap().get(turtleid) {
+        Some(turt) => turt.next_rx.clone(),
+        None => return Ok("WAIT".to_string()),
+    };
+    let mut next_rx = next_rx.lock().await;
+    let deadline = time::Instant::now() + config.next_timeout();
+    loop {
+        match time::timeout_at(deadline, next_rx.recv()).await {
+            // The worker gave up waiting for it
+            Ok(Some(req)) if req.is_cancelled() => (),
+            Ok(Some(req)) => return Ok(req.to_wire()),
+            Ok(None) => {
+                // Nothing is driving this turtle, don't let it spin
+                time::sleep(config.next_timeout()).await;
+                return Ok("WAIT".to_string());
+            }
+            Err(_) => return Ok("WAIT".to_string()),
+        }
     }
 }
 
-#[post("/<turtleid>", format = "json", data = "<body>")]
-async fn cmdcomplete(bot_net: &State<BotNet>, turtleid: usize, body: Json<TurtRawResponse>) {
-    let raw_resp = body.into_inner();
-    let resp: TurtResponse = raw_resp.into();
+#[post("/<turtleid>?<session>", format = "json", data = "<body>")]
+async fn cmdcomplete(
+    bot_net: &State<BotNet>,
+    _auth: TurtleAuth,
+    turtleid: usize,
+    session: Option<SessionId>,
+    body: Json<TurtRawResponse>,
+) -> Result<(), Status> {
+    // A response from an old session would be mistaken for the answer to the new worker's command
+    bot_net.check_session(turtleid, session)?;
+    // Passed on as is, the worker decides what to do with a response it can't read
+    let raw = body.into_inner();
+    let reply = (raw.seq(), TurtResponse::try_from(raw));
 
-    match bot_net.turtles.read().unwrap()[turtleid]
-        .cmdcomplete_tx
-        .send(resp)
-    {
-        Ok(_) => (),
-        Err(_) => println!("Turtle {turtleid} has disconnected!"),
+    let sent = match bot_net.turtles.read().unwrap().get(turtleid) {
+        Some(turt) => turt.cmdcomplete_tx.send(reply).is_ok(),
+        None => false,
     };
+    if !sent {
+        bot_net.emit(turtleid, TurtEventKind::Disconnected);
+    }
+    Ok(())
 }
 
-pub async fn run() {
-    let bot_net: BotNet = BotNet::new();
-    rocket::build()
+#[post("/", format = "json", data = "<body>")]
+async fn create_job(bot_net: &State<BotNet>, _auth: OperatorAuth, body: Json<JobConfig>) -> Json<JobId> {
+    Json(bot_net.add_job(body.into_inner()))
+}
+
+#[post("/<jobid>/start")]
+async fn start_job(bot_net: &State<BotNet>, _auth: OperatorAuth, jobid: JobId) -> Option<()> {
+    bot_net.start_job(jobid)
+}
+
+#[derive(serde::Deserialize)]
+struct Assignment {
+    job: JobId,
+}
+
+#[post("/<turtleid>/assign", format = "json", data = "<body>")]
+async fn assign(bot_net: &State<BotNet>, _auth: OperatorAuth, turtleid: usize, body: Json<Assignment>) -> Result<(), Status> {
+    bot_net.assign_turtle(turtleid, body.job)
+}
+
+#[get("/")]
+async fn status(bot_net: &State<BotNet>, _auth: OperatorAuth) -> Json<Vec<TurtStatus>> {
+    Json(bot_net.all_statuses())
+}
+
+#[get("/<turtleid>")]
+async fn turtle_status(bot_net: &State<BotNet>, _auth: OperatorAuth, turtleid: usize) -> Option<Json<TurtStatus>> {
+    bot_net.status(turtleid).map(Json)
+}
+
+#[derive(serde::Serialize)]
+struct Snapshot {
+    turtles: Vec<TurtStatus>,
+    jobs: Vec<JobSummary>,
+}
+
+/// Sends a snapshot of every turtle and job once a second, this is what the dashboard draws.
+#[get("/stream")]
+async fn status_stream<'a>(bot_net: &'a State<BotNet>, _auth: OperatorAuth, mut end: Shutdown) -> EventStream![Event + 'a] {
+    EventStream! {
+        let mut interval = time::interval(STATUS_STREAM_INTERVAL);
+        loop {
+            select! {
+                _ = interval.tick() => (),
+                _ = &mut end => break,
+            };
+            yield Event::json(&Snapshot {
+                turtles: bot_net.all_statuses(),
+                jobs: bot_net.job_summaries(),
+            });
+        }
+    }
+}
+
+/// Every event emitted by the turtles, as they happen.
+#[get("/")]
+async fn events<'a>(bot_net: &'a State<BotNet>, _auth: OperatorAuth, mut end: Shutdown) -> EventStream![Event + 'a] {
+    let mut rx = bot_net.events.subscribe();
+    EventStream! {
+        loop {
+            let event = select! {
+                event = rx.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut end => break,
+            };
+            yield Event::json(&event);
+        }
+    }
+}
+
+/// Prints the events that are worth reading in the terminal.
+async fn log_events(events: EventBus) {
+    let mut rx = events.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(event) => match event.kind {
+                TurtEventKind::Moved | TurtEventKind::Dug | TurtEventKind::Placed => (),
+                _ => println!("{event}"),
+            },
+            Err(RecvError::Closed) => break,
+            Err(RecvError::Lagged(n)) => println!("Missed {n} events!"),
+        }
+    }
+}
+
+fn save_map(map: &SharedWorldMap) {
+    if let Err(e) = map.write().unwrap().save() {
+        println!("Unable to save the world map: {e}");
+    }
+}
+
+/// Saves the parts of the world map that changed every now and then, so little is lost if the
+/// server is killed.
+async fn save_map_periodically(map: SharedWorldMap) {
+    let mut interval = time::interval(MAP_SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        save_map(&map);
+    }
+}
+
+/// Every block the turtles have seen between two corners, e.g. `/map?x1=0&y1=0&z1=0&x2=15&y2=255&z2=15`.
+#[get("/?<x1>&<y1>&<z1>&<x2>&<y2>&<z2>")]
+#[allow(clippy::too_many_arguments)]
+async fn export_map_area(
+    bot_net: &State<BotNet>,
+    _auth: OperatorAuth,
+    x1: i64,
+    y1: i64,
+    z1: i64,
+    x2: i64,
+    y2: i64,
+    z2: i64,
+) -> Json<Vec<MapEntry>> {
+    Json(bot_net.map.read().unwrap().entries_in(&Pos::new(x1, y1, z1), &Pos::new(x2, y2, z2)))
+}
+
+/// Every block the turtles have seen.
+#[get("/", rank = 2)]
+async fn export_map(bot_net: &State<BotNet>, _auth: OperatorAuth) -> Json<Vec<MapEntry>> {
+    Json(bot_net.map.read().unwrap().entries())
+}
+
+#[get("/")]
+async fn jobs(bot_net: &State<BotNet>, _auth: OperatorAuth) -> Json<Vec<JobSummary>> {
+    Json(bot_net.job_summaries())
+}
+
+/// The server with its config read from `Rocket.toml` and the environment.
+pub fn build() -> Rocket<Build> {
+    build_with(rocket::Config::figment())
+}
+
+/// The server with the given config, e.g. for tests that don't want to depend on `Rocket.toml`.
+pub fn build_with(figment: Figment) -> Rocket<Build> {
+    rocket::custom(figment)
         .mount("/register", routes![register])
         .mount("/next", routes![next])
         .mount("/cmdcomplete", routes![cmdcomplete])
-        .manage(bot_net)
+        .mount("/jobs", routes![jobs, create_job, start_job])
+        .mount("/turtles", routes![assign, turtle_status])
+        .mount("/status", routes![status, status_stream])
+        .mount("/events", routes![events])
+        .mount("/map", routes![export_map_area, export_map])
+        // Redirects `/dashboard` to `/dashboard/`, which the page's relative links to its assets need
+        .mount("/dashboard", FileServer::new(relative!("static"), Options::Index | Options::NormalizeDirs))
+        .manage(BotNet::new())
+        .attach(AdHoc::config::<ServerConfig>())
+        .attach(AdHoc::config::<AuthConfig>())
+        .attach(AdHoc::on_liftoff("Event log", |rocket| Box::pin(async move {
+            if let Some(bot_net) = rocket.state::<BotNet>() {
+                rocket::tokio::spawn(log_events(bot_net.events.clone()));
+            }
+        })))
+        .attach(AdHoc::on_liftoff("World map saving", |rocket| Box::pin(async move {
+            if let Some(bot_net) = rocket.state::<BotNet>() {
+                rocket::tokio::spawn(save_map_periodically(bot_net.map.clone()));
+            }
+        })))
+        .attach(AdHoc::on_shutdown("World map", |rocket| Box::pin(async move {
+            if let Some(bot_net) = rocket.state::<BotNet>() {
+                save_map(&bot_net.map);
+            }
+        })))
+        .attach(AdHoc::try_on_ignite("Auth check", |rocket| async move {
+            match rocket.state::<AuthConfig>().map(|a| a.check()) {
+                Some(Ok(())) => Ok(rocket),
+                Some(Err(e)) => {
+                    println!("Refusing to start: {e}");
+                    Err(rocket)
+                }
+                None => Err(rocket),
+            }
+        }))
+        .attach(AdHoc::on_liftoff("Auth warning", |rocket| Box::pin(async move {
+            if rocket.state::<AuthConfig>().is_some_and(|a| a.insecure()) {
+                println!("Running without tokens (insecure = true), anyone can control the turtles!");
+            }
+        })))
+}
+
+pub async fn run() {
+    let rocket = build();
+    if let Some(job) = JobConfig::load().expect("Unable to load job file") {
+        let bot_net = rocket.state::<BotNet>().unwrap();
+        let jobid = bot_net.add_job(job);
+        bot_net.start_job(jobid);
+    }
+    let _ = rocket
         .launch()
         .await
         .expect("Bye bye server...");
@@ -0,0 +1,73 @@
+//! A simulated world and turtles that answer the same commands as `protu.lua`, so that scripts can
+//! be run without Minecraft.
+//!
+//! ```ignore
+//! let world = SimWorld::shared();
+//! world.lock().unwrap().add_turtle(0, PosH::default());
+//! let turt = sim::connect(&world, 0);
+//! turt.mv_forw().await?;
+//! ```
+pub mod world;
+
+pub use world::{SharedWorld, SimStack, SimTurtle, SimWorld};
+
+use rocket::serde::json::serde_json;
+use rocket::tokio::sync::mpsc;
+
+use crate::turtle_core::command::TurtRequest;
+use crate::turtle_core::control::{TurtControl, TurtControlConfig};
+use crate::turtle_core::data::{TurtRawResponse, TurtResponse};
+use crate::turtle_core::error::TurtError;
+use crate::turtle_core::events::EventBus;
+use crate::turtle_core::reservations::{Reservations, SharedReservations};
+use crate::turtle_core::status::TurtStatus;
+use crate::turtle_core::world_map::{SharedWorldMap, WorldMap};
+use crate::TurtleIdentifier;
+
+/// Returns a `TurtControl` for a turtle that was added to the world. Its commands are answered by a
+/// task that runs until the `TurtControl` is dropped. The turtle gets a map and reservations of its
+/// own, kept in memory.
+pub fn connect(world: &SharedWorld, turtleid: TurtleIdentifier) -> TurtControl {
+    connect_with(
+        world,
+        turtleid,
+        EventBus::new(),
+        TurtControlConfig::default(),
+        WorldMap::new().shared(),
+        Reservations::new().shared(),
+    )
+}
+
+/// Pass the same `map` and `reservations` to turtles that should share what they see and stay out of
+/// each other's way.
+pub fn connect_with(
+    world: &SharedWorld,
+    turtleid: TurtleIdentifier,
+    events: EventBus,
+    config: TurtControlConfig,
+    map: SharedWorldMap,
+    reservations: SharedReservations,
+) -> TurtControl {
+    let (next_tx, mut next_rx) = mpsc::unbounded_channel::<TurtRequest>();
+    let (cmdcomplete_tx, cmdcomplete_rx) = mpsc::unbounded_channel();
+
+    let world = world.clone();
+    rocket::tokio::spawn(async move {
+        while let Some(req) = next_rx.recv().await {
+            if req.is_cancelled() {
+                continue;
+            }
+            let raw = world.lock().unwrap().exec(turtleid, &req.to_wire());
+            // Read back the same way the server reads what the turtle posts to `/cmdcomplete`
+            let reply = match serde_json::from_value::<TurtRawResponse>(raw) {
+                Ok(raw) => (raw.seq(), TurtResponse::try_from(raw)),
+                Err(e) => (None, Err(TurtError::MalformedResponse(e.to_string()))),
+            };
+            if cmdcomplete_tx.send(reply).is_err() {
+                break;
+            }
+        }
+    });
+
+    TurtControl::new(next_tx, cmdcomplete_rx, TurtStatus::shared(turtleid), events, config, map, reservations)
+}
@@ -0,0 +1,719 @@
+//! Voxel grid with blocks, chests and turtles. Anything that isn't set is air.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rocket::serde::json::{json, serde_json, Value};
+
+use crate::turtle_core::command::TurtCommand;
+use crate::turtle_core::data::NEVER_DIG_MSG;
+use crate::turtle_core::inventory::TURT_SLOTS;
+use crate::turtle_core::navigation::{Head, Pos, PosH};
+use crate::turtle_core::pathfinding::matches_block;
+use crate::TurtleIdentifier;
+
+pub type SharedWorld = Arc<Mutex<SimWorld>>;
+
+pub const CHEST: &str = "minecraft:chest";
+pub const CHEST_SLOTS: usize = 27;
+pub const STACK_SIZE: u32 = 64;
+/// What other turtles see when they inspect a turtle.
+pub const TURTLE_BLOCK: &str = "computercraft:turtle_normal";
+const UNBREAKABLE: &[&str] = &["minecraft:bedrock"];
+pub const CRAFTING_TABLE: &str = "minecraft:crafting_table";
+/// Items that can be equipped with `turtle.equipLeft()` and `turtle.equipRight()`.
+const UPGRADES: &[&str] = &[
+    CRAFTING_TABLE,
+    "minecraft:diamond_pickaxe",
+    "minecraft:diamond_axe",
+    "minecraft:diamond_shovel",
+    "minecraft:diamond_sword",
+    "minecraft:diamond_hoe",
+    "computercraft:wireless_modem_normal",
+    "computercraft:wireless_modem_advanced",
+];
+/// How much fuel one item gives.
+const FUEL_VALUES: &[(&str, u32)] = &[
+    ("minecraft:coal", 80),
+    ("minecraft:charcoal", 80),
+    ("minecraft:coal_block", 800),
+    ("minecraft:lava_bucket", 1000),
+    ("minecraft:oak_planks", 15),
+    ("minecraft:stick", 5),
+];
+
+type BlockPos = (i64, i64, i64);
+
+fn key(p: &Pos) -> BlockPos {
+    (p.x, p.y, p.z)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimStack {
+    pub name: String,
+    pub count: u32,
+}
+
+impl SimStack {
+    pub fn new(name: &str, count: u32) -> Self {
+        Self { name: name.to_string(), count }
+    }
+}
+
+/// Puts as much of `stack` into `slots` as fits, starting at `start`. Returns what didn't fit.
+fn insert(slots: &mut [Option<SimStack>], start: usize, mut stack: SimStack) -> Option<SimStack> {
+    let order = (start..slots.len()).chain(0..start);
+    // Top up existing stacks first, then fill empty slots
+    for i in order.clone() {
+        if let Some(s) = &mut slots[i] {
+            if s.name == stack.name && s.count < STACK_SIZE {
+                let n = stack.count.min(STACK_SIZE - s.count);
+                s.count += n;
+                stack.count -= n;
+            }
+        }
+        if stack.count == 0 {
+            return None;
+        }
+    }
+    for i in order {
+        if slots[i].is_none() {
+            let n = stack.count.min(STACK_SIZE);
+            slots[i] = Some(SimStack::new(&stack.name, n));
+            stack.count -= n;
+        }
+        if stack.count == 0 {
+            return None;
+        }
+    }
+    Some(stack)
+}
+
+/// Splits off `count` items, or the whole stack if there's no count.
+fn split(mut stack: SimStack, count: Option<u8>) -> (SimStack, Option<SimStack>) {
+    let n = count.map_or(stack.count, |c| stack.count.min(c as u32));
+    let left = SimStack::new(&stack.name, stack.count - n);
+    stack.count = n;
+    (stack, (left.count > 0).then_some(left))
+}
+
+/// Puts the parts of a stack back together, they always hold the same item.
+fn merge(a: Option<SimStack>, b: Option<SimStack>) -> Option<SimStack> {
+    match (a, b) {
+        (Some(mut a), Some(b)) => {
+            a.count += b.count;
+            (a.count > 0).then_some(a)
+        }
+        (Some(s), None) | (None, Some(s)) => (s.count > 0).then_some(s),
+        (None, None) => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimTurtle {
+    pub pos: PosH,
+    pub slots: Vec<Option<SimStack>>,
+    /// 0-indexed
+    pub selected: usize,
+    /// Only used up if the world has a `fuel_limit`.
+    pub fuel: u32,
+    pub left: Option<SimStack>,
+    pub right: Option<SimStack>,
+}
+
+impl SimTurtle {
+    fn new(pos: PosH) -> Self {
+        Self {
+            pos,
+            slots: vec![None; TURT_SLOTS],
+            selected: 0,
+            fuel: 0,
+            left: None,
+            right: None,
+        }
+    }
+
+    pub fn count(&self, name: &str) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|s| s.name == name)
+            .map(|s| s.count)
+            .sum()
+    }
+
+    /// The block in front, above or below the turtle.
+    fn target(&self, dir: Dir) -> Pos {
+        let p = &self.pos;
+        match dir {
+            Dir::Forw => match p.h {
+                Head::N => Pos::new(p.x, p.y, p.z - 1),
+                Head::E => Pos::new(p.x + 1, p.y, p.z),
+                Head::S => Pos::new(p.x, p.y, p.z + 1),
+                Head::W => Pos::new(p.x - 1, p.y, p.z),
+            },
+            Dir::Back => match p.h {
+                Head::N => Pos::new(p.x, p.y, p.z + 1),
+                Head::E => Pos::new(p.x - 1, p.y, p.z),
+                Head::S => Pos::new(p.x, p.y, p.z - 1),
+                Head::W => Pos::new(p.x + 1, p.y, p.z),
+            },
+            Dir::Up => Pos::new(p.x, p.y + 1, p.z),
+            Dir::Down => Pos::new(p.x, p.y - 1, p.z),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Dir {
+    Forw,
+    Back,
+    Up,
+    Down,
+}
+
+/// What a command returns: the code and output that `protu.lua` would post to `/cmdcomplete`.
+type Output = (i32, Value);
+
+fn ok(out: Value) -> Output {
+    (0, out)
+}
+
+fn fail(msg: &str) -> Output {
+    (0, json!([false, msg]))
+}
+
+#[derive(Debug)]
+pub struct SimWorld {
+    blocks: HashMap<BlockPos, String>,
+    chests: HashMap<BlockPos, Vec<Option<SimStack>>>,
+    turtles: HashMap<TurtleIdentifier, SimTurtle>,
+    /// Whether `gps.locate()` finds the turtles.
+    pub gps: bool,
+    /// Everything the turtles printed.
+    pub printed: Vec<(TurtleIdentifier, String)>,
+    /// Moving costs fuel if set, otherwise fuel is unlimited.
+    pub fuel_limit: Option<u32>,
+    /// Tags of each kind of block, returned when the block is inspected.
+    pub tags: HashMap<String, Vec<String>>,
+}
+
+impl Default for SimWorld {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimWorld {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            chests: HashMap::new(),
+            turtles: HashMap::new(),
+            gps: true,
+            printed: vec![],
+            fuel_limit: None,
+            tags: HashMap::from([
+                (TURTLE_BLOCK.to_string(), vec!["computercraft:turtle".to_string()]),
+                (CHEST.to_string(), vec!["forge:chests".to_string()]),
+            ]),
+        }
+    }
+
+    pub fn shared() -> SharedWorld {
+        Arc::new(Mutex::new(Self::new()))
+    }
+
+    pub fn block(&self, p: &Pos) -> Option<&str> {
+        self.blocks.get(&key(p)).map(|b| b.as_str())
+    }
+
+    /// Turtles also take up a block.
+    fn occupied(&self, p: &Pos) -> Option<&str> {
+        if let Some(b) = self.block(p) {
+            return Some(b);
+        }
+        self.turtles
+            .values()
+            .any(|t| t.pos.x == p.x && t.pos.y == p.y && t.pos.z == p.z)
+            .then_some(TURTLE_BLOCK)
+    }
+
+    pub fn set_block(&mut self, p: &Pos, name: &str) {
+        if name == CHEST {
+            self.chests.entry(key(p)).or_insert_with(|| vec![None; CHEST_SLOTS]);
+        } else {
+            self.chests.remove(&key(p));
+        }
+        self.blocks.insert(key(p), name.to_string());
+    }
+
+    pub fn remove_block(&mut self, p: &Pos) -> Option<String> {
+        self.chests.remove(&key(p));
+        self.blocks.remove(&key(p))
+    }
+
+    /// Sets every block in the box between both corners, inclusive.
+    pub fn fill(&mut self, p1: &Pos, p2: &Pos, name: &str) {
+        for x in p1.x.min(p2.x)..=p1.x.max(p2.x) {
+            for y in p1.y.min(p2.y)..=p1.y.max(p2.y) {
+                for z in p1.z.min(p2.z)..=p1.z.max(p2.z) {
+                    self.set_block(&Pos::new(x, y, z), name);
+                }
+            }
+        }
+    }
+
+    /// Number of blocks in the box between both corners that aren't air.
+    pub fn count_blocks(&self, p1: &Pos, p2: &Pos) -> usize {
+        self.blocks
+            .keys()
+            .filter(|(x, y, z)| {
+                (p1.x.min(p2.x)..=p1.x.max(p2.x)).contains(x)
+                    && (p1.y.min(p2.y)..=p1.y.max(p2.y)).contains(y)
+                    && (p1.z.min(p2.z)..=p1.z.max(p2.z)).contains(z)
+            })
+            .count()
+    }
+
+    /// Places a chest if there isn't one already. Returns what didn't fit.
+    pub fn put_in_chest(&mut self, p: &Pos, stack: SimStack) -> Option<SimStack> {
+        if self.block(p) != Some(CHEST) {
+            self.set_block(p, CHEST);
+        }
+        insert(self.chests.get_mut(&key(p)).unwrap(), 0, stack)
+    }
+
+    pub fn chest(&self, p: &Pos) -> Option<&Vec<Option<SimStack>>> {
+        self.chests.get(&key(p))
+    }
+
+    pub fn add_turtle(&mut self, turtleid: TurtleIdentifier, pos: PosH) {
+        self.turtles.insert(turtleid, SimTurtle::new(pos));
+    }
+
+    pub fn turtle(&self, turtleid: TurtleIdentifier) -> Option<&SimTurtle> {
+        self.turtles.get(&turtleid)
+    }
+
+    pub fn turtle_mut(&mut self, turtleid: TurtleIdentifier) -> Option<&mut SimTurtle> {
+        self.turtles.get_mut(&turtleid)
+    }
+
+    /// Puts items in the turtle's inventory, as if a player had.
+    pub fn give(&mut self, turtleid: TurtleIdentifier, stack: SimStack) -> Option<SimStack> {
+        let turt = self.turtles.get_mut(&turtleid)?;
+        insert(&mut turt.slots, 0, stack)
+    }
+
+    /// Runs a command sent by the server, like `protu.lua` does. Returns the JSON it would post.
+    pub fn exec(&mut self, turtleid: TurtleIdentifier, cmd: &str) -> Value {
+        let (code, out) = match TurtCommand::from_wire(cmd) {
+            Ok(TurtCommand::Exit) => ok(json!("Turtle leaving the chat.")),
+            Ok(TurtCommand::Batch { stop_on_fail, cmds }) => self.exec_batch(turtleid, stop_on_fail, &cmds),
+            Ok(cmd) => self.exec_one(turtleid, &cmd),
+            Err(e) => (-1, json!(format!("Bad request: {}", e))),
+        };
+        // Sent back so the server can tell which command this answers
+        let seq = serde_json::from_str::<Value>(cmd).ok().and_then(|c| c.get("seq").cloned());
+        json!({ "code": code, "out": out, "seq": seq })
+    }
+
+    fn exec_batch(&mut self, turtleid: TurtleIdentifier, stop_on_fail: bool, cmds: &[TurtCommand]) -> Output {
+        let mut results = vec![];
+        for cmd in cmds {
+            let (code, out) = self.exec_one(turtleid, cmd);
+            let failed = code != 0 || out[0] == json!(false);
+            results.push(json!({ "code": code, "out": out }));
+            if stop_on_fail && failed {
+                break;
+            }
+        }
+        ok(Value::Array(results))
+    }
+
+    fn exec_one(&mut self, turtleid: TurtleIdentifier, cmd: &TurtCommand) -> Output {
+        if !self.turtles.contains_key(&turtleid) {
+            return (-1, json!(format!("No turtle {} in the world", turtleid)));
+        }
+
+        match cmd {
+            TurtCommand::Forward => self.mv(turtleid, Dir::Forw),
+            TurtCommand::Back => self.mv(turtleid, Dir::Back),
+            TurtCommand::Up => self.mv(turtleid, Dir::Up),
+            TurtCommand::Down => self.mv(turtleid, Dir::Down),
+            TurtCommand::TurnLeft => self.turn(turtleid, false),
+            TurtCommand::TurnRight => self.turn(turtleid, true),
+            TurtCommand::Dig => self.dig(turtleid, Dir::Forw),
+            TurtCommand::DigUp => self.dig(turtleid, Dir::Up),
+            TurtCommand::DigDown => self.dig(turtleid, Dir::Down),
+            TurtCommand::DigUnless { never } => self.dig_unless(turtleid, Dir::Forw, never),
+            TurtCommand::DigUpUnless { never } => self.dig_unless(turtleid, Dir::Up, never),
+            TurtCommand::DigDownUnless { never } => self.dig_unless(turtleid, Dir::Down, never),
+            TurtCommand::Inspect => self.inspect(turtleid, Dir::Forw),
+            TurtCommand::InspectUp => self.inspect(turtleid, Dir::Up),
+            TurtCommand::InspectDown => self.inspect(turtleid, Dir::Down),
+            TurtCommand::Place => self.place(turtleid, Dir::Forw),
+            TurtCommand::PlaceUp => self.place(turtleid, Dir::Up),
+            TurtCommand::PlaceDown => self.place(turtleid, Dir::Down),
+            TurtCommand::Suck { count } => self.suck(turtleid, Dir::Forw, *count),
+            TurtCommand::SuckUp { count } => self.suck(turtleid, Dir::Up, *count),
+            TurtCommand::SuckDown { count } => self.suck(turtleid, Dir::Down, *count),
+            TurtCommand::Drop { count } => self.drop(turtleid, Dir::Forw, *count),
+            TurtCommand::DropUp { count } => self.drop(turtleid, Dir::Up, *count),
+            TurtCommand::DropDown { count } => self.drop(turtleid, Dir::Down, *count),
+            TurtCommand::Detect => self.detect(turtleid, Dir::Forw),
+            TurtCommand::DetectUp => self.detect(turtleid, Dir::Up),
+            TurtCommand::DetectDown => self.detect(turtleid, Dir::Down),
+            TurtCommand::Compare => self.compare(turtleid, Dir::Forw),
+            TurtCommand::CompareUp => self.compare(turtleid, Dir::Up),
+            TurtCommand::CompareDown => self.compare(turtleid, Dir::Down),
+            // There are no mobs here
+            TurtCommand::Attack | TurtCommand::AttackUp | TurtCommand::AttackDown => {
+                fail("Nothing to attack here")
+            }
+            TurtCommand::Select { slot } => self.select(turtleid, *slot),
+            TurtCommand::GetItemDetail { slot } => self.item_detail(turtleid, *slot),
+            TurtCommand::GetItemCount { slot } => self.item_count(turtleid, *slot, false),
+            TurtCommand::GetItemSpace { slot } => self.item_count(turtleid, *slot, true),
+            TurtCommand::GetSelectedSlot => ok(json!([self.turt(turtleid).selected + 1])),
+            TurtCommand::CompareTo { slot } => self.compare_to(turtleid, *slot),
+            TurtCommand::TransferTo { slot, count } => self.transfer_to(turtleid, *slot, *count),
+            TurtCommand::EquipLeft => self.equip(turtleid, false),
+            TurtCommand::EquipRight => self.equip(turtleid, true),
+            TurtCommand::Craft { .. } => self.craft(turtleid),
+            TurtCommand::GetFuelLevel => match self.fuel_limit {
+                Some(_) => ok(json!([self.turt(turtleid).fuel])),
+                None => ok(json!(["unlimited"])),
+            },
+            TurtCommand::GetFuelLimit => match self.fuel_limit {
+                Some(limit) => ok(json!([limit])),
+                None => ok(json!(["unlimited"])),
+            },
+            TurtCommand::Refuel { count } => self.refuel(turtleid, *count),
+            TurtCommand::GpsLocate => self.locate(turtleid),
+            TurtCommand::Print { msg } => {
+                self.printed.push((turtleid, msg.clone()));
+                ok(json!([]))
+            }
+            // There is no Lua here
+            TurtCommand::Eval { code } => (-2, json!(format!("Bad function: {}", code))),
+            TurtCommand::Batch { .. } | TurtCommand::Exit => (-1, json!("Can't be nested in a batch")),
+        }
+    }
+
+    fn turt(&mut self, turtleid: TurtleIdentifier) -> &mut SimTurtle {
+        self.turtles.get_mut(&turtleid).unwrap()
+    }
+
+    fn target(&self, turtleid: TurtleIdentifier, dir: Dir) -> Pos {
+        self.turtles[&turtleid].target(dir)
+    }
+
+    fn mv(&mut self, turtleid: TurtleIdentifier, dir: Dir) -> Output {
+        let target = self.target(turtleid, dir);
+        if self.occupied(&target).is_some() {
+            return fail("Movement obstructed");
+        }
+        let limited = self.fuel_limit.is_some();
+        let turt = self.turt(turtleid);
+        if limited {
+            if turt.fuel == 0 {
+                return fail("Out of fuel");
+            }
+            turt.fuel -= 1;
+        }
+        turt.pos.x = target.x;
+        turt.pos.y = target.y;
+        turt.pos.z = target.z;
+        ok(json!([true]))
+    }
+
+    fn turn(&mut self, turtleid: TurtleIdentifier, right: bool) -> Output {
+        let turt = self.turt(turtleid);
+        turt.pos.h = match (&turt.pos.h, right) {
+            (Head::N, true) | (Head::S, false) => Head::E,
+            (Head::E, true) | (Head::W, false) => Head::S,
+            (Head::S, true) | (Head::N, false) => Head::W,
+            (Head::W, true) | (Head::E, false) => Head::N,
+        };
+        ok(json!([true]))
+    }
+
+    fn dig(&mut self, turtleid: TurtleIdentifier, dir: Dir) -> Output {
+        let target = self.target(turtleid, dir);
+        match self.occupied(&target) {
+            None => return fail("Nothing to dig here"),
+            Some(TURTLE_BLOCK) => return fail("Nothing to dig here"),
+            Some(b) if UNBREAKABLE.contains(&b) => return fail("Cannot break unbreakable block"),
+            Some(_) => (),
+        }
+        let block = self.remove_block(&target).unwrap();
+        let turt = self.turt(turtleid);
+        let selected = turt.selected;
+        // Whatever doesn't fit is dropped on the ground, and lost
+        insert(&mut turt.slots, selected, SimStack::new(&block, 1));
+        ok(json!([true]))
+    }
+
+    fn dig_unless(&mut self, turtleid: TurtleIdentifier, dir: Dir, never: &[String]) -> Output {
+        let target = self.target(turtleid, dir);
+        if let Some(b) = self.occupied(&target) {
+            let tags = self.tags.get(b).cloned().unwrap_or_default();
+            if matches_block(never, b, &tags) {
+                return fail(&format!("{}{}", NEVER_DIG_MSG, b));
+            }
+        }
+        self.dig(turtleid, dir)
+    }
+
+    fn inspect(&mut self, turtleid: TurtleIdentifier, dir: Dir) -> Output {
+        let target = self.target(turtleid, dir);
+        match self.occupied(&target) {
+            Some(b) => {
+                let tags: serde_json::Map<String, Value> = self.tags
+                    .get(b)
+                    .into_iter()
+                    .flatten()
+                    .map(|t| (t.clone(), Value::Bool(true)))
+                    .collect();
+                ok(json!([true, { "name": b, "tags": tags }]))
+            }
+            None => fail("No block to inspect"),
+        }
+    }
+
+    fn place(&mut self, turtleid: TurtleIdentifier, dir: Dir) -> Output {
+        let target = self.target(turtleid, dir);
+        if self.occupied(&target).is_some() {
+            return fail("Cannot place block here");
+        }
+        let turt = self.turt(turtleid);
+        let selected = turt.selected;
+        let name = match &mut turt.slots[selected] {
+            Some(s) => {
+                s.count -= 1;
+                s.name.clone()
+            }
+            None => return fail("No items to place"),
+        };
+        if turt.slots[selected].as_ref().map(|s| s.count) == Some(0) {
+            turt.slots[selected] = None;
+        }
+        self.set_block(&target, &name);
+        ok(json!([true]))
+    }
+
+    fn suck(&mut self, turtleid: TurtleIdentifier, dir: Dir, count: Option<u8>) -> Output {
+        let target = self.target(turtleid, dir);
+        let chest = match self.chests.get_mut(&key(&target)) {
+            Some(chest) => chest,
+            None => return fail("No items to take"),
+        };
+        let slot = match chest.iter().position(|s| s.is_some()) {
+            Some(slot) => slot,
+            None => return fail("No items to take"),
+        };
+        let (stack, left) = split(chest[slot].take().unwrap(), count);
+
+        let turt = self.turtles.get_mut(&turtleid).unwrap();
+        let taken = stack.count;
+        let rest = insert(&mut turt.slots, turt.selected, stack);
+        let rest_count = rest.as_ref().map_or(0, |r| r.count);
+        let chest = self.chests.get_mut(&key(&target)).unwrap();
+        chest[slot] = merge(rest, left);
+        match rest_count == taken {
+            true => fail("No space for items"),
+            false => ok(json!([true])),
+        }
+    }
+
+    fn drop(&mut self, turtleid: TurtleIdentifier, dir: Dir, count: Option<u8>) -> Output {
+        let target = self.target(turtleid, dir);
+        let turt = self.turtles.get_mut(&turtleid).unwrap();
+        let selected = turt.selected;
+        let (stack, left) = match turt.slots[selected].take() {
+            Some(stack) => split(stack, count),
+            None => return fail("No items to drop"),
+        };
+        let dropped = stack.count;
+        // Without a chest the items end up on the ground, and are lost
+        let rest = match self.chests.get_mut(&key(&target)) {
+            Some(chest) => insert(chest, 0, stack),
+            None => None,
+        };
+        let rest_count = rest.as_ref().map_or(0, |r| r.count);
+        let turt = self.turt(turtleid);
+        turt.slots[selected] = merge(rest, left);
+        match rest_count == dropped {
+            true => fail("No space for items"),
+            false => ok(json!([true])),
+        }
+    }
+
+    fn detect(&mut self, turtleid: TurtleIdentifier, dir: Dir) -> Output {
+        let target = self.target(turtleid, dir);
+        ok(json!([self.occupied(&target).is_some()]))
+    }
+
+    /// An empty slot is the same as air.
+    fn compare(&mut self, turtleid: TurtleIdentifier, dir: Dir) -> Output {
+        let target = self.target(turtleid, dir);
+        let block = self.occupied(&target).map(|b| b.to_string());
+        let turt = self.turt(turtleid);
+        let item = turt.slots[turt.selected].as_ref().map(|s| s.name.clone());
+        ok(json!([block == item]))
+    }
+
+    /// Slot arguments are 1-indexed, like in Lua.
+    fn slot_arg(slot: u8) -> Result<usize, Output> {
+        match slot as usize {
+            n if (1..=TURT_SLOTS).contains(&n) => Ok(n - 1),
+            _ => Err((-1, json!("bad argument #1 (number out of range)"))),
+        }
+    }
+
+    fn select(&mut self, turtleid: TurtleIdentifier, slot: u8) -> Output {
+        match Self::slot_arg(slot) {
+            Ok(slot) => {
+                self.turt(turtleid).selected = slot;
+                ok(json!([true]))
+            }
+            Err(e) => e,
+        }
+    }
+
+    fn item_detail(&mut self, turtleid: TurtleIdentifier, slot: u8) -> Output {
+        let slot = match Self::slot_arg(slot) {
+            Ok(slot) => slot,
+            Err(e) => return e,
+        };
+        match &self.turt(turtleid).slots[slot] {
+            Some(s) => ok(json!([{ "name": s.name, "count": s.count }])),
+            None => ok(json!([])),
+        }
+    }
+
+    fn item_count(&mut self, turtleid: TurtleIdentifier, slot: u8, space: bool) -> Output {
+        let slot = match Self::slot_arg(slot) {
+            Ok(slot) => slot,
+            Err(e) => return e,
+        };
+        let count = self.turt(turtleid).slots[slot].as_ref().map_or(0, |s| s.count);
+        match space {
+            true => ok(json!([STACK_SIZE - count])),
+            false => ok(json!([count])),
+        }
+    }
+
+    fn compare_to(&mut self, turtleid: TurtleIdentifier, slot: u8) -> Output {
+        let slot = match Self::slot_arg(slot) {
+            Ok(slot) => slot,
+            Err(e) => return e,
+        };
+        let turt = self.turt(turtleid);
+        let name = |i: usize| turt.slots[i].as_ref().map(|s| s.name.as_str());
+        ok(json!([name(turt.selected) == name(slot)]))
+    }
+
+    fn transfer_to(&mut self, turtleid: TurtleIdentifier, slot: u8, count: Option<u8>) -> Output {
+        let slot = match Self::slot_arg(slot) {
+            Ok(slot) => slot,
+            Err(e) => return e,
+        };
+        let turt = self.turt(turtleid);
+        let selected = turt.selected;
+        let (stack, left) = match turt.slots[selected].take() {
+            Some(stack) => split(stack, count),
+            None => return fail("No items to transfer"),
+        };
+        if slot == selected {
+            turt.slots[selected] = merge(Some(stack), left);
+            return ok(json!([true]));
+        }
+        let moved = match &turt.slots[slot] {
+            None => stack.count,
+            Some(s) if s.name == stack.name => stack.count.min(STACK_SIZE - s.count),
+            Some(_) => 0,
+        };
+        if moved == 0 {
+            turt.slots[selected] = merge(Some(stack), left);
+            return fail("No space for items");
+        }
+        let rest = SimStack::new(&stack.name, stack.count - moved);
+        let existing = turt.slots[slot].as_ref().map_or(0, |s| s.count);
+        turt.slots[slot] = Some(SimStack::new(&stack.name, existing + moved));
+        turt.slots[selected] = merge(Some(rest), left);
+        ok(json!([true]))
+    }
+
+    /// Swaps one of the selected items with the upgrade on that side.
+    fn equip(&mut self, turtleid: TurtleIdentifier, right: bool) -> Output {
+        let turt = self.turt(turtleid);
+        let selected = turt.selected;
+        let (item, left) = match turt.slots[selected].take() {
+            Some(stack) if !UPGRADES.contains(&stack.name.as_str()) => {
+                turt.slots[selected] = Some(stack);
+                return fail("Not a valid upgrade");
+            }
+            Some(stack) => {
+                let (item, left) = split(stack, Some(1));
+                (Some(item), left)
+            }
+            None => (None, None),
+        };
+        turt.slots[selected] = left;
+        let side = match right {
+            true => &mut turt.right,
+            false => &mut turt.left,
+        };
+        let old = std::mem::replace(side, item);
+        if let Some(old) = old {
+            // Whatever doesn't fit is dropped on the ground, and lost
+            insert(&mut turt.slots, selected, old);
+        }
+        ok(json!([true]))
+    }
+
+    /// There are no recipes here, so crafting only checks for a crafting table.
+    fn craft(&mut self, turtleid: TurtleIdentifier) -> Output {
+        let turt = self.turt(turtleid);
+        let has_table = [&turt.left, &turt.right]
+            .iter()
+            .any(|s| s.as_ref().map(|s| s.name.as_str()) == Some(CRAFTING_TABLE));
+        match has_table {
+            true => fail("No matching recipes"),
+            false => (-1, json!("attempt to call field 'craft' (a nil value)")),
+        }
+    }
+
+    fn refuel(&mut self, turtleid: TurtleIdentifier, count: Option<u8>) -> Output {
+        let limit = self.fuel_limit;
+        let turt = self.turt(turtleid);
+        let selected = turt.selected;
+        let stack = match turt.slots[selected].take() {
+            Some(stack) => stack,
+            None => return fail("No items to combust"),
+        };
+        let value = match FUEL_VALUES.iter().find(|(name, _)| *name == stack.name) {
+            Some((_, value)) => *value,
+            None => {
+                turt.slots[selected] = Some(stack);
+                return fail("Items not combustible");
+            }
+        };
+        let (burnt, left) = split(stack, count);
+        turt.slots[selected] = left;
+        if let Some(limit) = limit {
+            turt.fuel = (turt.fuel + burnt.count * value).min(limit);
+        }
+        ok(json!([true]))
+    }
+
+    fn locate(&mut self, turtleid: TurtleIdentifier) -> Output {
+        if !self.gps {
+            return ok(json!([]));
+        }
+        let p = &self.turt(turtleid).pos;
+        ok(json!([p.x, p.y, p.z]))
+    }
+}
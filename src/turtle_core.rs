@@ -2,4 +2,12 @@ pub mod navigation;
 pub mod inventory;
 pub mod control;
 pub mod data;
-pub mod file_system_storage;
\ No newline at end of file
+pub mod file_system_storage;
+pub mod status;
+pub mod events;
+pub mod error;
+pub mod command;
+pub mod fuel;
+pub mod world_map;
+pub mod reservations;
+pub mod pathfinding;
\ No newline at end of file
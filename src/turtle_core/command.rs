@@ -0,0 +1,241 @@
+//! Commands the server sends to the turtle. They are sent as JSON, e.g. `{"cmd":"select","slot":3}`,
+//! and `protu.lua` maps each one to a turtle API call, so that no code is sent unless `Eval` is used.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum TurtCommand {
+    Forward,
+    Back,
+    Up,
+    Down,
+    TurnLeft,
+    TurnRight,
+    Dig,
+    DigUp,
+    DigDown,
+    /// Digs unless the block matches one of `never`, see `NavConfig::never_digs`. Saves inspecting
+    /// the block in a round-trip of its own first.
+    DigUnless { never: Vec<String> },
+    DigUpUnless { never: Vec<String> },
+    DigDownUnless { never: Vec<String> },
+    Inspect,
+    InspectUp,
+    InspectDown,
+    Place,
+    PlaceUp,
+    PlaceDown,
+    /// Takes a whole stack unless `count` is given.
+    Suck {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u8>,
+    },
+    SuckUp {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u8>,
+    },
+    SuckDown {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u8>,
+    },
+    /// Drops the whole selected stack unless `count` is given.
+    Drop {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u8>,
+    },
+    DropUp {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u8>,
+    },
+    DropDown {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u8>,
+    },
+    Detect,
+    DetectUp,
+    DetectDown,
+    /// Whether the block is the same as the selected item.
+    Compare,
+    CompareUp,
+    CompareDown,
+    Attack,
+    AttackUp,
+    AttackDown,
+    /// 1-indexed, like the turtle API.
+    Select { slot: u8 },
+    /// 1-indexed, like the turtle API.
+    GetItemDetail { slot: u8 },
+    /// 1-indexed, like the turtle API.
+    GetItemCount { slot: u8 },
+    /// 1-indexed, like the turtle API.
+    GetItemSpace { slot: u8 },
+    GetSelectedSlot,
+    /// Whether the selected item is the same as the one in `slot`, 1-indexed.
+    CompareTo { slot: u8 },
+    /// Moves the selected stack, or `count` items of it, to `slot`, 1-indexed.
+    TransferTo {
+        slot: u8,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u8>,
+    },
+    EquipLeft,
+    EquipRight,
+    /// Crafts as many items as possible unless `limit` is given. Needs a crafting table equipped.
+    Craft {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<u8>,
+    },
+    GetFuelLevel,
+    GetFuelLimit,
+    /// Burns the whole selected stack unless `count` is given.
+    Refuel {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u8>,
+    },
+    GpsLocate,
+    Print { msg: String },
+    /// Runs `return <code>` on the turtle. Anything the other commands can do should be done with them.
+    Eval { code: String },
+    /// Runs the commands in order, see `TurtBatch`.
+    Batch { stop_on_fail: bool, cmds: Vec<TurtCommand> },
+    /// Makes the turtle register again.
+    Exit,
+}
+
+impl TurtCommand {
+    /// What is sent to the turtle.
+    pub fn to_wire(&self) -> String {
+        rocket::serde::json::to_string(self).expect("Commands can always be serialized")
+    }
+
+    pub fn from_wire(s: &str) -> Result<Self, String> {
+        rocket::serde::json::from_str(s).map_err(|e| e.to_string())
+    }
+
+    /// Whether sending the command again does no harm if the first answer got lost, because it
+    /// doesn't change the turtle or the world.
+    pub fn is_repeatable(&self) -> bool {
+        match self {
+            TurtCommand::Inspect | TurtCommand::InspectUp | TurtCommand::InspectDown
+            | TurtCommand::Detect | TurtCommand::DetectUp | TurtCommand::DetectDown
+            | TurtCommand::Compare | TurtCommand::CompareUp | TurtCommand::CompareDown
+            | TurtCommand::Select { .. } | TurtCommand::GetItemDetail { .. } | TurtCommand::GetItemCount { .. }
+            | TurtCommand::GetItemSpace { .. } | TurtCommand::GetSelectedSlot | TurtCommand::CompareTo { .. }
+            | TurtCommand::GetFuelLevel | TurtCommand::GetFuelLimit | TurtCommand::GpsLocate => true,
+            TurtCommand::Batch { cmds, .. } => cmds.iter().all(|c| c.is_repeatable()),
+            _ => false,
+        }
+    }
+}
+
+/// Sequence number of a command, which the turtle sends back with its answer.
+pub type Seq = u64;
+
+/// A command on its way to the turtle. The sequence number tells its answer apart from late answers
+/// to earlier commands.
+#[derive(Debug, Clone)]
+pub struct TurtRequest {
+    pub seq: Seq,
+    pub cmd: TurtCommand,
+    /// Set once the worker stopped waiting for the answer.
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TurtRequest {
+    pub fn new(seq: Seq, cmd: TurtCommand) -> Self {
+        Self {
+            seq,
+            cmd,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// A cancelled command that the turtle hasn't picked up yet is never handed out.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// The command as JSON with the sequence number added, e.g. `{"cmd":"forward","seq":12}`.
+    pub fn to_wire(&self) -> String {
+        let mut v = rocket::serde::json::serde_json::to_value(&self.cmd).expect("Commands can always be serialized");
+        v["seq"] = self.seq.into();
+        v.to_string()
+    }
+}
+
+/// The equivalent Lua, for logs and the status page.
+impl std::fmt::Display for TurtCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let call = |f: &mut std::fmt::Formatter<'_>, name: &str| write!(f, "turtle.{}()", name);
+        let call_n = |f: &mut std::fmt::Formatter<'_>, name: &str, n: &Option<u8>| match n {
+            Some(n) => write!(f, "turtle.{}({})", name, n),
+            None => write!(f, "turtle.{}()", name),
+        };
+        match self {
+            TurtCommand::Forward => call(f, "forward"),
+            TurtCommand::Back => call(f, "back"),
+            TurtCommand::Up => call(f, "up"),
+            TurtCommand::Down => call(f, "down"),
+            TurtCommand::TurnLeft => call(f, "turnLeft"),
+            TurtCommand::TurnRight => call(f, "turnRight"),
+            TurtCommand::Dig => call(f, "dig"),
+            TurtCommand::DigUp => call(f, "digUp"),
+            TurtCommand::DigDown => call(f, "digDown"),
+            TurtCommand::DigUnless { never } => write!(f, "turtle.dig() unless {:?}", never),
+            TurtCommand::DigUpUnless { never } => write!(f, "turtle.digUp() unless {:?}", never),
+            TurtCommand::DigDownUnless { never } => write!(f, "turtle.digDown() unless {:?}", never),
+            TurtCommand::Inspect => call(f, "inspect"),
+            TurtCommand::InspectUp => call(f, "inspectUp"),
+            TurtCommand::InspectDown => call(f, "inspectDown"),
+            TurtCommand::Place => call(f, "place"),
+            TurtCommand::PlaceUp => call(f, "placeUp"),
+            TurtCommand::PlaceDown => call(f, "placeDown"),
+            TurtCommand::Suck { count } => call_n(f, "suck", count),
+            TurtCommand::SuckUp { count } => call_n(f, "suckUp", count),
+            TurtCommand::SuckDown { count } => call_n(f, "suckDown", count),
+            TurtCommand::Drop { count } => call_n(f, "drop", count),
+            TurtCommand::DropUp { count } => call_n(f, "dropUp", count),
+            TurtCommand::DropDown { count } => call_n(f, "dropDown", count),
+            TurtCommand::Detect => call(f, "detect"),
+            TurtCommand::DetectUp => call(f, "detectUp"),
+            TurtCommand::DetectDown => call(f, "detectDown"),
+            TurtCommand::Compare => call(f, "compare"),
+            TurtCommand::CompareUp => call(f, "compareUp"),
+            TurtCommand::CompareDown => call(f, "compareDown"),
+            TurtCommand::Attack => call(f, "attack"),
+            TurtCommand::AttackUp => call(f, "attackUp"),
+            TurtCommand::AttackDown => call(f, "attackDown"),
+            TurtCommand::Select { slot } => write!(f, "turtle.select({})", slot),
+            TurtCommand::GetItemDetail { slot } => write!(f, "turtle.getItemDetail({})", slot),
+            TurtCommand::GetItemCount { slot } => write!(f, "turtle.getItemCount({})", slot),
+            TurtCommand::GetItemSpace { slot } => write!(f, "turtle.getItemSpace({})", slot),
+            TurtCommand::GetSelectedSlot => call(f, "getSelectedSlot"),
+            TurtCommand::CompareTo { slot } => write!(f, "turtle.compareTo({})", slot),
+            TurtCommand::TransferTo { slot, count: None } => write!(f, "turtle.transferTo({})", slot),
+            TurtCommand::TransferTo { slot, count: Some(count) } => {
+                write!(f, "turtle.transferTo({}, {})", slot, count)
+            }
+            TurtCommand::EquipLeft => call(f, "equipLeft"),
+            TurtCommand::EquipRight => call(f, "equipRight"),
+            TurtCommand::Craft { limit } => call_n(f, "craft", limit),
+            TurtCommand::GetFuelLevel => call(f, "getFuelLevel"),
+            TurtCommand::GetFuelLimit => call(f, "getFuelLimit"),
+            TurtCommand::Refuel { count } => call_n(f, "refuel", count),
+            TurtCommand::GpsLocate => write!(f, "gps.locate()"),
+            TurtCommand::Print { msg } => write!(f, "print({:?})", msg),
+            TurtCommand::Eval { code } => write!(f, "{}", code),
+            TurtCommand::Batch { cmds, .. } => {
+                let cmds = cmds.iter().map(|c| c.to_string()).collect::<Vec<String>>();
+                write!(f, "{}", cmds.join("; "))
+            }
+            TurtCommand::Exit => write!(f, "EXIT"),
+        }
+    }
+}
@@ -1,149 +1,688 @@
-use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use rocket::tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use rocket::tokio::sync::Mutex;
+use rocket::tokio::time::{self, Instant};
 
-use super::data::{TurtResponse, TurtMovement, TurtInspect, TurtSlot};
-use anyhow::Result;
+use super::command::{Seq, TurtCommand, TurtRequest};
+use super::data::{MoveFailure, TurtFuel, TurtResponse, TurtMovement, TurtInspect, TurtSlot};
+use super::error::TurtError;
+use super::inventory::TURT_SLOTS;
+use super::events::{EventBus, TurtEvent, TurtEventKind};
+use super::status::{now_millis, SharedStatus, TurtStatus};
+use super::reservations::SharedReservations;
+use super::world_map::{Cell, SharedWorldMap};
 
-pub type TurtFunc<'a, R> = fn(&'a TurtControl<'a>) -> R;
+/// What the server received from the turtle, or why it couldn't make sense of it.
+pub type TurtResult = Result<TurtResponse, TurtError>;
+
+/// A response along with the sequence number of the command it answers, see `TurtRequest`.
+pub type TurtReply = (Option<Seq>, TurtResult);
+
+/// Read from `[default.turtle_control]` in `Rocket.toml`.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TurtControlConfig {
+    /// How long to wait for the turtle to answer a command.
+    #[serde(default = "TurtControlConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// How many times commands that are safe to repeat are retried after timing out.
+    #[serde(default = "TurtControlConfig::default_retries")]
+    pub retries: usize,
+    /// How many times `TurtNavigation` tries to get past something in the way before giving up.
+    #[serde(default = "TurtControlConfig::default_move_attempts")]
+    pub move_attempts: usize,
+}
+
+impl TurtControlConfig {
+    fn default_timeout_ms() -> u64 {
+        30_000
+    }
+
+    fn default_retries() -> usize {
+        2
+    }
+
+    fn default_move_attempts() -> usize {
+        10
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+impl Default for TurtControlConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: Self::default_timeout_ms(),
+            retries: Self::default_retries(),
+            move_attempts: Self::default_move_attempts(),
+        }
+    }
+}
+
+/// Turns a 0-indexed slot into the 1-indexed slot the turtle API takes.
+fn slot_arg(slot: u8) -> Result<u8, TurtError> {
+    if slot as usize >= TURT_SLOTS {
+        return Err(TurtError::InvalidSlot(slot));
+    }
+    Ok(slot + 1)
+}
+
+/// Which way the turtle should move, dig or inspect, relative to where it is facing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurtDir {
+    Forw,
+    Up,
+    Down,
+}
+
+fn dig_unless(dir: TurtDir, never: &[String]) -> TurtCommand {
+    let never = never.to_vec();
+    match dir {
+        TurtDir::Forw => TurtCommand::DigUnless { never },
+        TurtDir::Up => TurtCommand::DigUpUnless { never },
+        TurtDir::Down => TurtCommand::DigDownUnless { never },
+    }
+}
+
+/// What the response to `cmd` says about the block in front, above or below the turtle, if anything,
+/// along with the block's tags if it was inspected.
+fn observed_block(cmd: &TurtCommand, resp: &TurtResponse) -> Option<(TurtDir, Cell, Vec<String>)> {
+    let dir = match cmd {
+        TurtCommand::Inspect | TurtCommand::Detect | TurtCommand::Dig | TurtCommand::DigUnless { .. }
+        | TurtCommand::Place | TurtCommand::Forward => TurtDir::Forw,
+        TurtCommand::InspectUp | TurtCommand::DetectUp | TurtCommand::DigUp | TurtCommand::DigUpUnless { .. }
+        | TurtCommand::PlaceUp | TurtCommand::Up => TurtDir::Up,
+        TurtCommand::InspectDown | TurtCommand::DetectDown | TurtCommand::DigDown
+        | TurtCommand::DigDownUnless { .. } | TurtCommand::PlaceDown | TurtCommand::Down => TurtDir::Down,
+        _ => return None,
+    };
+    let cell = match cmd {
+        TurtCommand::Inspect | TurtCommand::InspectUp | TurtCommand::InspectDown => {
+            let inspect = TurtInspect::try_from(resp.clone()).ok()?;
+            return Some(match inspect.block() {
+                Some(b) => (dir, Cell::Block(b.clone()), inspect.tags().to_vec()),
+                None => (dir, Cell::Air, vec![]),
+            });
+        }
+        TurtCommand::Detect | TurtCommand::DetectUp | TurtCommand::DetectDown => match resp.clone().bool().ok()? {
+            true => Cell::Solid,
+            false => Cell::Air,
+        },
+        TurtCommand::Place | TurtCommand::PlaceUp | TurtCommand::PlaceDown => match resp.success() {
+            true => Cell::Solid,
+            false => return None,
+        },
+        // Digging or moving, which leaves air behind if it worked
+        _ => match TurtMovement::try_from(resp.clone()).ok()?.failure() {
+            None | Some(MoveFailure::NothingToDig) => Cell::Air,
+            Some(MoveFailure::OutOfFuel) => return None,
+            Some(f) if f.is_permanent() => Cell::Blocked,
+            Some(_) => return None,
+        },
+    };
+    Some((dir, cell, vec![]))
+}
 
 #[derive(Debug)]
-pub struct TurtControl<'a> {
-    next_tx: mpsc::Sender<String>,
-    cmdcomplete_rx: &'a mpsc::Receiver<TurtResponse>,
+pub struct TurtControl {
+    next_tx: UnboundedSender<TurtRequest>,
+    /// Only one command is in flight at a time, the lock is held until it is answered.
+    cmdcomplete_rx: Mutex<UnboundedReceiver<TurtReply>>,
+    last_seq: AtomicU64,
+    /// Set once another worker is about to take over the turtle, see `stop_when`.
+    stop: Arc<AtomicBool>,
+    status: SharedStatus,
+    events: EventBus,
+    config: TurtControlConfig,
+    /// Shared with every other turtle, updated with what this one finds out about the blocks around it.
+    map: SharedWorldMap,
+    /// Shared with every other turtle, see `TurtNavigation`.
+    reservations: SharedReservations,
 }
 
-impl<'a> TurtControl<'a> {
+impl TurtControl {
     pub fn new(
-        next_tx: mpsc::Sender<String>,
-        cmdcomplete_rx: &'a mpsc::Receiver<TurtResponse>,
+        next_tx: UnboundedSender<TurtRequest>,
+        cmdcomplete_rx: UnboundedReceiver<TurtReply>,
+        status: SharedStatus,
+        events: EventBus,
+        config: TurtControlConfig,
+        map: SharedWorldMap,
+        reservations: SharedReservations,
     ) -> Self {
         Self {
             next_tx,
-            cmdcomplete_rx,
+            cmdcomplete_rx: Mutex::new(cmdcomplete_rx),
+            last_seq: AtomicU64::new(0),
+            stop: Arc::new(AtomicBool::new(false)),
+            status,
+            events,
+            config,
+            map,
+            reservations,
         }
     }
 
-    pub fn make_req_t<T>(&self, cmd: &str) -> Result<T, <T as TryFrom<TurtResponse>>::Error>
+    /// Stops sending commands once `stop` is set, failing them with `TurtError::Disconnected`
+    /// instead. The worker only stops between commands, so it never loses track of a move the
+    /// turtle made.
+    pub fn stop_when(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    /// Publishes an event, tagged with the turtle's current position and last command.
+    pub fn emit(&self, kind: TurtEventKind) {
+        let event = TurtEvent::new(&self.status.read().unwrap(), kind);
+        self.events.emit(event);
+    }
+
+    pub fn config(&self) -> &TurtControlConfig {
+        &self.config
+    }
+
+    /// What all turtles have seen of the world.
+    pub fn map(&self) -> &SharedWorldMap {
+        &self.map
+    }
+
+    /// Which blocks the turtles are in or about to move into.
+    pub fn reservations(&self) -> &SharedReservations {
+        &self.reservations
+    }
+
+    pub fn update_status<F: FnOnce(&mut TurtStatus)>(&self, f: F) {
+        f(&mut self.status.write().unwrap());
+    }
+
+    pub async fn make_req_t<T>(&self, cmd: TurtCommand) -> Result<T, TurtError>
         where
-            T: TryFrom<TurtResponse>,
+            T: TryFrom<TurtResponse, Error = TurtError>,
     {
-        self.next_tx.send(cmd.to_string()).unwrap();
-        let resp = self.cmdcomplete_rx.recv().unwrap();
-        T::try_from(resp)
+        T::try_from(self.make_req(cmd).await?)
     }
 
-    pub fn make_req(&self, cmd: &str) -> TurtResponse {
-        self.next_tx.send(cmd.to_string()).unwrap();
-        self.cmdcomplete_rx.recv().unwrap()
+    /// Same as `make_req_t`, for commands that are safe to send again.
+    pub async fn make_req_t_retry<T>(&self, cmd: TurtCommand) -> Result<T, TurtError>
+        where
+            T: TryFrom<TurtResponse, Error = TurtError>,
+    {
+        T::try_from(self.make_req_retry(cmd).await?)
     }
 
-    pub fn suck(&self) -> TurtResponse {
-        self.make_req("turtle.suck()")
+    pub async fn make_req(&self, cmd: TurtCommand) -> Result<TurtResponse, TurtError> {
+        self.make_req_timeout(cmd, self.config.timeout()).await
     }
 
-    pub fn suck_up(&self) -> TurtResponse {
-        self.make_req("turtle.suckUp()")
+    /// Retries the command if it times out, as long as it is safe to send again, such as inspecting
+    /// or `gps.locate()`. Anything else, like moving, is only sent once.
+    pub async fn make_req_retry(&self, cmd: TurtCommand) -> Result<TurtResponse, TurtError> {
+        let retries = match cmd.is_repeatable() {
+            true => self.config.retries,
+            false => 0,
+        };
+        let mut attempt = 0;
+        loop {
+            match self.make_req(cmd.clone()).await {
+                Err(TurtError::Timeout { .. }) if attempt < retries => attempt += 1,
+                resp => return resp,
+            }
+        }
     }
 
-    pub fn suck_down(&self) -> TurtResponse {
-        self.make_req("turtle.suckDown()")
+    /// Fails with `TurtError::Disconnected` once the turtle has registered again or the worker was
+    /// told to stop, which should end the worker.
+    ///
+    /// On timeout the command is cancelled, so it isn't handed to the turtle any more if it hasn't
+    /// been yet. If it has, its answer comes too late and is thrown away like any answer whose
+    /// sequence number isn't the one of the command being waited on.
+    pub async fn make_req_timeout(&self, cmd: TurtCommand, timeout: Duration) -> Result<TurtResponse, TurtError> {
+        let mut cmdcomplete_rx = self.cmdcomplete_rx.lock().await;
+        if self.stop.load(Ordering::Relaxed) {
+            return Err(TurtError::Disconnected);
+        }
+        let deadline = Instant::now() + timeout;
+
+        let cmd_str = cmd.to_string();
+        self.update_status(|s| s.last_cmd = Some(cmd_str.clone()));
+        let req = TurtRequest::new(self.last_seq.fetch_add(1, Ordering::Relaxed) + 1, cmd.clone());
+        if self.next_tx.send(req.clone()).is_err() {
+            return Err(TurtError::Disconnected);
+        }
+        loop {
+            match time::timeout_at(deadline, cmdcomplete_rx.recv()).await {
+                Ok(Some((Some(seq), resp))) if seq == req.seq => {
+                    self.set_reachable(true);
+                    self.update_status(|s| s.last_response = Some(now_millis()));
+                    if let Ok(resp) = &resp {
+                        self.observe(&cmd, resp);
+                    }
+                    return resp;
+                }
+                // A late answer to a command that timed out, or to one the turtle couldn't read
+                Ok(Some(_)) => continue,
+                Ok(None) => return Err(TurtError::Disconnected),
+                Err(_) => {
+                    req.cancel();
+                    self.set_reachable(false);
+                    return Err(TurtError::Timeout { cmd: cmd_str, after: timeout });
+                }
+            }
+        }
     }
 
-    pub fn mv_forw(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.forward()")
+    /// Records what the response says about the blocks around the turtle in the world map, once its
+    /// position is known. The position isn't updated until a batch is done, so commands in a batch
+    /// after the turtle moved or turned are left out.
+    fn observe(&self, cmd: &TurtCommand, resp: &TurtResponse) {
+        let (turtleid, pos) = {
+            let status = self.status.read().unwrap();
+            match &status.pos {
+                Some(pos) => (status.id, pos.clone()),
+                None => return,
+            }
+        };
+        let mut observed = vec![];
+        match cmd {
+            TurtCommand::Batch { cmds, .. } => {
+                let resps = match resp.clone().batch() {
+                    Ok(resps) => resps,
+                    Err(_) => return,
+                };
+                for (cmd, resp) in cmds.iter().zip(resps.iter()) {
+                    observed.extend(observed_block(cmd, resp));
+                    if matches!(cmd, TurtCommand::Forward | TurtCommand::Back | TurtCommand::Up
+                        | TurtCommand::Down | TurtCommand::TurnLeft | TurtCommand::TurnRight) {
+                        break;
+                    }
+                }
+            }
+            cmd => observed.extend(observed_block(cmd, resp)),
+        }
+
+        let mut map = self.map.write().unwrap();
+        for (dir, cell, tags) in observed {
+            if let Cell::Block(b) = &cell {
+                map.set_tags(b, &tags);
+            }
+            map.observe(&pos.neighbour(dir), cell, turtleid);
+        }
     }
 
-    pub fn mv_back(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.back()")
+    fn set_reachable(&self, reachable: bool) {
+        let changed = {
+            let mut status = self.status.write().unwrap();
+            let changed = status.reachable != reachable;
+            status.reachable = reachable;
+            changed
+        };
+        if changed {
+            self.emit(match reachable {
+                true => TurtEventKind::Reachable,
+                false => TurtEventKind::Unreachable,
+            });
+        }
     }
 
-    pub fn mv_up(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.up()")
+    /// Same as `make_req`, but emits `kind` if the turtle reports that the command succeeded.
+    async fn make_req_emit(&self, cmd: TurtCommand, kind: TurtEventKind) -> Result<TurtResponse, TurtError> {
+        let resp = self.make_req(cmd).await?;
+        if resp.success() {
+            self.emit(kind);
+        }
+        Ok(resp)
     }
 
-    pub fn mv_down(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.down()")
+    /// Starts a batch of commands that are sent to the turtle in one round-trip.
+    pub fn batch(&self) -> TurtBatch<'_> {
+        TurtBatch::new(self)
     }
 
-    pub fn turn_left(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.turnLeft()")
+    pub async fn suck(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::Suck { count: None }).await
     }
 
-    pub fn turn_right(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.turnRight()")
+    pub async fn suck_up(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::SuckUp { count: None }).await
     }
 
-    pub fn dig_forw(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.dig()")
+    pub async fn suck_down(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::SuckDown { count: None }).await
     }
 
-    pub fn dig_down(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.digDown()")
+    pub async fn mv_forw(&self) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::Forward).await
     }
 
-    pub fn dig_up(&self) -> Result<TurtMovement> {
-        self.make_req_t("turtle.digUp()")
+    pub async fn mv_back(&self) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::Back).await
     }
 
-    pub fn insp_forw(&self) -> Result<TurtInspect> {
-        self.make_req_t("turtle.inspect()")
+    pub async fn mv_up(&self) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::Up).await
     }
 
-    pub fn insp_up(&self) -> Result<TurtInspect> {
-        self.make_req_t("turtle.inspectUp()")
+    pub async fn mv_down(&self) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::Down).await
     }
 
-    pub fn insp_down(&self) -> Result<TurtInspect> {
-        self.make_req_t("turtle.inspectDown()")
+    pub async fn turn_left(&self) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::TurnLeft).await
     }
 
-    /// 0-indexed
-    pub fn inv_select(&self, slot: u8) -> TurtResponse {
-        if slot > 15 {
-            panic!("Invalid slot number!");
+    pub async fn turn_right(&self) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::TurnRight).await
+    }
+
+    pub async fn dig_forw(&self) -> Result<TurtMovement, TurtError> {
+        TurtMovement::try_from(self.make_req_emit(TurtCommand::Dig, TurtEventKind::Dug).await?)
+    }
+
+    pub async fn dig_down(&self) -> Result<TurtMovement, TurtError> {
+        TurtMovement::try_from(self.make_req_emit(TurtCommand::DigDown, TurtEventKind::Dug).await?)
+    }
+
+    pub async fn dig_up(&self) -> Result<TurtMovement, TurtError> {
+        TurtMovement::try_from(self.make_req_emit(TurtCommand::DigUp, TurtEventKind::Dug).await?)
+    }
+
+    /// Digs in `dir` unless the block matches one of `never`, see `TurtCommand::DigUnless`.
+    pub async fn dig_unless(&self, dir: TurtDir, never: &[String]) -> Result<TurtMovement, TurtError> {
+        TurtMovement::try_from(self.make_req_emit(dig_unless(dir, never), TurtEventKind::Dug).await?)
+    }
+
+    pub async fn insp_forw(&self) -> Result<TurtInspect, TurtError> {
+        self.make_req_t_retry(TurtCommand::Inspect).await
+    }
+
+    pub async fn insp_up(&self) -> Result<TurtInspect, TurtError> {
+        self.make_req_t_retry(TurtCommand::InspectUp).await
+    }
+
+    pub async fn insp_down(&self) -> Result<TurtInspect, TurtError> {
+        self.make_req_t_retry(TurtCommand::InspectDown).await
+    }
+
+    /// Moves in `dir`, see `mv_forw`, `mv_up` and `mv_down`.
+    pub async fn mv(&self, dir: TurtDir) -> Result<TurtMovement, TurtError> {
+        match dir {
+            TurtDir::Forw => self.mv_forw().await,
+            TurtDir::Up => self.mv_up().await,
+            TurtDir::Down => self.mv_down().await,
         }
-        self.make_req(&format!("turtle.select({})", slot + 1))
     }
 
-    /// 0-indexed
-    pub fn inv_item_detail(&self, slot: u8) -> Option<TurtSlot> {
-        if slot > 15 {
-            panic!("Invalid slot number!");
+    pub async fn dig(&self, dir: TurtDir) -> Result<TurtMovement, TurtError> {
+        match dir {
+            TurtDir::Forw => self.dig_forw().await,
+            TurtDir::Up => self.dig_up().await,
+            TurtDir::Down => self.dig_down().await,
         }
-        match self.make_req_t::<TurtSlot>(&format!("turtle.getItemDetail({})", slot + 1)) {
-            Ok(s) => Some(s),
-            Err(_) => None,
+    }
+
+    pub async fn insp(&self, dir: TurtDir) -> Result<TurtInspect, TurtError> {
+        match dir {
+            TurtDir::Forw => self.insp_forw().await,
+            TurtDir::Up => self.insp_up().await,
+            TurtDir::Down => self.insp_down().await,
         }
     }
 
-    pub fn inv_drop_forw(&self) -> TurtResponse {
-        self.make_req("turtle.drop()")
+    /// Takes up to `count` items from the inventory in `dir`.
+    pub async fn suck_count(&self, dir: TurtDir, count: u8) -> Result<TurtResponse, TurtError> {
+        let count = Some(count);
+        self.make_req(match dir {
+            TurtDir::Forw => TurtCommand::Suck { count },
+            TurtDir::Up => TurtCommand::SuckUp { count },
+            TurtDir::Down => TurtCommand::SuckDown { count },
+        }).await
     }
 
-    pub fn inv_drop_down(&self) -> TurtResponse {
-        self.make_req("turtle.dropDown()")
+    /// Whether there is a block in `dir`.
+    pub async fn detect(&self, dir: TurtDir) -> Result<bool, TurtError> {
+        self.make_req_retry(match dir {
+            TurtDir::Forw => TurtCommand::Detect,
+            TurtDir::Up => TurtCommand::DetectUp,
+            TurtDir::Down => TurtCommand::DetectDown,
+        }).await?.bool()
     }
 
-    pub fn inv_drop_up(&self) -> TurtResponse {
-        self.make_req("turtle.dropUp()")
+    /// Whether the block in `dir` is the same as the selected item.
+    pub async fn compare(&self, dir: TurtDir) -> Result<bool, TurtError> {
+        self.make_req_retry(match dir {
+            TurtDir::Forw => TurtCommand::Compare,
+            TurtDir::Up => TurtCommand::CompareUp,
+            TurtDir::Down => TurtCommand::CompareDown,
+        }).await?.bool()
     }
 
-    pub fn place_forw(&self) -> TurtResponse {
-        self.make_req("turtle.place()")
+    pub async fn attack(&self, dir: TurtDir) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(match dir {
+            TurtDir::Forw => TurtCommand::Attack,
+            TurtDir::Up => TurtCommand::AttackUp,
+            TurtDir::Down => TurtCommand::AttackDown,
+        }).await
     }
 
-    pub fn place_up(&self) -> TurtResponse {
-        self.make_req("turtle.placeUp()")
+    /// 0-indexed
+    pub async fn inv_select(&self, slot: u8) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::Select { slot: slot_arg(slot)? }).await
     }
 
-    pub fn place_down(&self) -> TurtResponse {
-        self.make_req("turtle.placeDown()")
+    /// 0-indexed
+    pub async fn inv_selected_slot(&self) -> Result<u8, TurtError> {
+        let slot = self.make_req_retry(TurtCommand::GetSelectedSlot).await?.number()?;
+        match slot as usize {
+            n if (1..=TURT_SLOTS).contains(&n) => Ok(n as u8 - 1),
+            _ => Err(TurtError::MalformedResponse(format!("Invalid slot {}", slot))),
+        }
     }
 
-    pub fn print(&self, msg: &str) -> TurtResponse {
-        self.make_req(format!("print(\"{msg}\")").as_str())
+    /// 0-indexed
+    pub async fn inv_item_detail(&self, slot: u8) -> Result<Option<TurtSlot>, TurtError> {
+        TurtSlot::from_detail(self.make_req_retry(TurtCommand::GetItemDetail { slot: slot_arg(slot)? }).await?)
+    }
+
+    /// 0-indexed
+    pub async fn inv_item_count(&self, slot: u8) -> Result<u32, TurtError> {
+        self.make_req_retry(TurtCommand::GetItemCount { slot: slot_arg(slot)? }).await?.number()
+    }
+
+    /// How many more items fit in the slot, 0-indexed.
+    pub async fn inv_item_space(&self, slot: u8) -> Result<u32, TurtError> {
+        self.make_req_retry(TurtCommand::GetItemSpace { slot: slot_arg(slot)? }).await?.number()
+    }
+
+    /// Whether the selected item is the same as the one in `slot`, 0-indexed.
+    pub async fn inv_compare_to(&self, slot: u8) -> Result<bool, TurtError> {
+        self.make_req_retry(TurtCommand::CompareTo { slot: slot_arg(slot)? }).await?.bool()
+    }
+
+    /// Moves the selected stack, or `count` items of it, to `slot`, 0-indexed.
+    pub async fn inv_transfer_to(&self, slot: u8, count: Option<u8>) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::TransferTo { slot: slot_arg(slot)?, count }).await
+    }
+
+    pub async fn inv_drop_forw(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::Drop { count: None }).await
+    }
+
+    pub async fn inv_drop_down(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::DropDown { count: None }).await
+    }
+
+    pub async fn inv_drop_up(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::DropUp { count: None }).await
+    }
+
+    /// Drops up to `count` items of the selected stack into `dir`.
+    pub async fn inv_drop_count(&self, dir: TurtDir, count: u8) -> Result<TurtResponse, TurtError> {
+        let count = Some(count);
+        self.make_req(match dir {
+            TurtDir::Forw => TurtCommand::Drop { count },
+            TurtDir::Up => TurtCommand::DropUp { count },
+            TurtDir::Down => TurtCommand::DropDown { count },
+        }).await
+    }
+
+    /// Swaps the selected item with the left upgrade.
+    pub async fn equip_left(&self) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::EquipLeft).await
+    }
+
+    /// Swaps the selected item with the right upgrade.
+    pub async fn equip_right(&self) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::EquipRight).await
+    }
+
+    /// Crafts with the items in the inventory, needs a crafting table equipped.
+    pub async fn craft(&self, limit: Option<u8>) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::Craft { limit }).await
+    }
+
+    pub async fn fuel_level(&self) -> Result<TurtFuel, TurtError> {
+        self.make_req_t_retry(TurtCommand::GetFuelLevel).await
+    }
+
+    pub async fn fuel_limit(&self) -> Result<TurtFuel, TurtError> {
+        self.make_req_t_retry(TurtCommand::GetFuelLimit).await
+    }
+
+    /// Burns the selected stack, or `count` items of it.
+    pub async fn refuel(&self, count: Option<u8>) -> Result<TurtMovement, TurtError> {
+        self.make_req_t(TurtCommand::Refuel { count }).await
+    }
+
+    pub async fn place_forw(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req_emit(TurtCommand::Place, TurtEventKind::Placed).await
+    }
+
+    pub async fn place_up(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req_emit(TurtCommand::PlaceUp, TurtEventKind::Placed).await
+    }
+
+    pub async fn place_down(&self) -> Result<TurtResponse, TurtError> {
+        self.make_req_emit(TurtCommand::PlaceDown, TurtEventKind::Placed).await
+    }
+
+    pub async fn print(&self, msg: &str) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::Print { msg: msg.to_string() }).await
+    }
+
+    /// Runs `return <code>` on the turtle, for anything the other commands can't do.
+    pub async fn eval(&self, code: &str) -> Result<TurtResponse, TurtError> {
+        self.make_req(TurtCommand::Eval { code: code.to_string() }).await
     }
 
     pub fn disconnect(&self) {
-        self.next_tx.send("EXIT".to_string()).unwrap();
+        let seq = self.last_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        let _ = self.next_tx.send(TurtRequest::new(seq, TurtCommand::Exit));
+        self.emit(TurtEventKind::Disconnected);
+    }
+}
+
+/// Commands queued up to be executed by the turtle in order, in a single round-trip.
+///
+/// ```ignore
+/// let resps = turt.batch().dig_up().dig_down().place_down().run().await;
+/// ```
+#[derive(Debug)]
+pub struct TurtBatch<'b> {
+    turt: &'b TurtControl,
+    cmds: Vec<(TurtCommand, Option<TurtEventKind>)>,
+    stop_on_fail: bool,
+    /// Reported by `run`, so that the builder can be chained.
+    error: Option<TurtError>,
+}
+
+impl<'b> TurtBatch<'b> {
+    fn new(turt: &'b TurtControl) -> Self {
+        Self {
+            turt,
+            cmds: vec![],
+            stop_on_fail: false,
+            error: None,
+        }
+    }
+
+    /// Stop executing the batch at the first command that fails or returns `false`.
+    pub fn stop_on_fail(mut self) -> Self {
+        self.stop_on_fail = true;
+        self
+    }
+
+    pub fn add(mut self, cmd: TurtCommand) -> Self {
+        self.cmds.push((cmd, None));
+        self
+    }
+
+    fn add_emit(mut self, cmd: TurtCommand, kind: TurtEventKind) -> Self {
+        self.cmds.push((cmd, Some(kind)));
+        self
+    }
+
+    pub fn dig_forw(self) -> Self {
+        self.add_emit(TurtCommand::Dig, TurtEventKind::Dug)
+    }
+
+    pub fn dig_up(self) -> Self {
+        self.add_emit(TurtCommand::DigUp, TurtEventKind::Dug)
+    }
+
+    pub fn dig_down(self) -> Self {
+        self.add_emit(TurtCommand::DigDown, TurtEventKind::Dug)
+    }
+
+    /// Digs in `dir` unless the block matches one of `never`, see `TurtCommand::DigUnless`.
+    pub fn dig_unless(self, dir: TurtDir, never: &[String]) -> Self {
+        self.add_emit(dig_unless(dir, never), TurtEventKind::Dug)
+    }
+
+    /// 0-indexed
+    pub fn inv_select(mut self, slot: u8) -> Self {
+        match slot_arg(slot) {
+            Ok(slot) => self.add(TurtCommand::Select { slot }),
+            Err(e) => {
+                self.error.get_or_insert(e);
+                self
+            }
+        }
+    }
+
+    pub fn place_forw(self) -> Self {
+        self.add_emit(TurtCommand::Place, TurtEventKind::Placed)
+    }
+
+    pub fn place_up(self) -> Self {
+        self.add_emit(TurtCommand::PlaceUp, TurtEventKind::Placed)
+    }
+
+    pub fn place_down(self) -> Self {
+        self.add_emit(TurtCommand::PlaceDown, TurtEventKind::Placed)
+    }
+
+    /// Returns one response per executed command.
+    pub async fn run(self) -> Result<Vec<TurtResponse>, TurtError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.cmds.is_empty() {
+            return Ok(vec![]);
+        }
+        let (cmds, kinds): (Vec<TurtCommand>, Vec<Option<TurtEventKind>>) = self.cmds.into_iter().unzip();
+        let req = TurtCommand::Batch { stop_on_fail: self.stop_on_fail, cmds };
+
+        let resps = self.turt.make_req(req).await?.batch()?;
+        for (kind, resp) in kinds.into_iter().zip(resps.iter()) {
+            if let Some(kind) = kind {
+                if resp.success() {
+                    self.turt.emit(kind);
+                }
+            }
+        }
+        Ok(resps)
     }
 }
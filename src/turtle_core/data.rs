@@ -1,6 +1,9 @@
 //! Data that is passed between the turtle (client) and server.
+pub use fuel::TurtFuel;
 pub use inventory::TurtSlot;
-pub use world::{TurtInspect, TurtMovement};
+pub use world::{MoveFailure, TurtInspect, TurtMovement, NEVER_DIG_MSG};
+use super::command::Seq;
+use super::error::TurtError;
 
 #[derive(Clone, Debug)]
 pub enum TurtResponse {
@@ -9,27 +12,130 @@ pub enum TurtResponse {
     Ok(rocket::serde::json::Value),
 }
 
+impl TurtResponse {
+    /// Whether the first value returned by the command is `true`, which is how the turtle API
+    /// reports success.
+    pub fn success(&self) -> bool {
+        match self {
+            TurtResponse::Ok(v) => v[0].as_bool() == Some(true),
+            _ => false,
+        }
+    }
+
+    /// The values returned by the command, or the error the turtle ran into.
+    pub fn value(self) -> Result<rocket::serde::json::Value, TurtError> {
+        match self {
+            TurtResponse::Ok(v) => Ok(v),
+            TurtResponse::BadReq(e) => Err(TurtError::BadReq(e)),
+            TurtResponse::BadCode(e) => Err(TurtError::BadCode(e)),
+        }
+    }
+
+    /// For commands that only return a bool, such as `turtle.detect()`.
+    pub fn bool(self) -> Result<bool, TurtError> {
+        self.value()?[0].as_bool().ok_or_else(|| malformed("Not a bool"))
+    }
+
+    /// For commands that only return a number, such as `turtle.getItemCount()`.
+    pub fn number(self) -> Result<u32, TurtError> {
+        let n = self.value()?[0].as_u64().ok_or_else(|| malformed("Not a number"))?;
+        u32::try_from(n).map_err(|_| malformed("Number out of range"))
+    }
+
+    /// Splits the response to a batch into the responses of each command. Commands after the first
+    /// failure are missing if the batch stopped on failure.
+    pub fn batch(self) -> Result<Vec<TurtResponse>, TurtError> {
+        let v = self.value()?;
+        let resps = rocket::serde::json::serde_json::from_value::<Vec<TurtRawResponse>>(v)
+            .map_err(|e| TurtError::MalformedResponse(e.to_string()))?;
+        resps.into_iter().map(TurtResponse::try_from).collect()
+    }
+}
+
 #[derive(serde::Deserialize, Debug)]
 pub struct TurtRawResponse {
     code: i32,
     out: rocket::serde::json::Value,
+    /// Of the command this answers, missing if the turtle couldn't read the command.
+    #[serde(default)]
+    seq: Option<Seq>,
 }
 
-impl Into<TurtResponse> for TurtRawResponse {
-    fn into(self) -> TurtResponse {
-        match self.code {
-            0 => TurtResponse::Ok(self.out),
-            -1 => TurtResponse::BadReq(self.out.to_string()),
-            -2 => TurtResponse::BadCode(self.out.to_string()),
-            _ => panic!("Invalid code!"),
+impl TurtRawResponse {
+    pub fn seq(&self) -> Option<Seq> {
+        self.seq
+    }
+}
+
+impl TryFrom<TurtRawResponse> for TurtResponse {
+    type Error = TurtError;
+    fn try_from(raw: TurtRawResponse) -> Result<Self, Self::Error> {
+        match raw.code {
+            0 => Ok(TurtResponse::Ok(raw.out)),
+            -1 => Ok(TurtResponse::BadReq(raw.out.to_string())),
+            -2 => Ok(TurtResponse::BadCode(raw.out.to_string())),
+            code => Err(TurtError::MalformedResponse(format!("Invalid code {}", code))),
+        }
+    }
+}
+
+fn malformed(msg: &str) -> TurtError {
+    TurtError::MalformedResponse(msg.to_string())
+}
+
+mod fuel {
+    use super::{malformed, TurtResponse};
+    use crate::turtle_core::error::TurtError;
+
+    /// Returned by `turtle.getFuelLevel()` and `turtle.getFuelLimit()`.
+    #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TurtFuel {
+        /// Fuel is disabled in the server config, so moving is free.
+        Unlimited,
+        Level(u32),
+    }
+
+    impl TurtFuel {
+        /// `None` if fuel is unlimited.
+        pub fn level(&self) -> Option<u32> {
+            match self {
+                TurtFuel::Unlimited => None,
+                TurtFuel::Level(n) => Some(*n),
+            }
+        }
+
+        /// Whether there is enough fuel to move `blocks` times.
+        pub fn enough_for(&self, blocks: u32) -> bool {
+            !matches!(self, TurtFuel::Level(n) if *n < blocks)
+        }
+    }
+
+    impl TryFrom<rocket::serde::json::Value> for TurtFuel {
+        type Error = TurtError;
+        fn try_from(value: rocket::serde::json::Value) -> Result<Self, Self::Error> {
+            let v = &value[0];
+            if v.as_str() == Some("unlimited") {
+                return Ok(TurtFuel::Unlimited);
+            }
+            let n = v.as_u64().ok_or_else(|| malformed("Not a fuel level"))?;
+            Ok(TurtFuel::Level(u32::try_from(n).map_err(|_| malformed("Fuel level out of range"))?))
+        }
+    }
+
+    impl TryFrom<TurtResponse> for TurtFuel {
+        type Error = TurtError;
+        fn try_from(value: TurtResponse) -> Result<Self, Self::Error> {
+            value.value()?.try_into()
         }
     }
 }
 
 mod inventory {
-    use super::TurtResponse;
+    use super::{malformed, TurtResponse};
+    use crate::turtle_core::error::TurtError;
 
-    #[derive(serde::Deserialize, Debug, Clone)]
+    #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
     pub struct TurtSlot {
         count: i32,
         name: String,
@@ -51,38 +157,31 @@ mod inventory {
         pub fn reduce_count(&mut self, n: i32) {
             self.count -= n;
         }
-    }
 
-    impl TryInto<TurtSlot> for rocket::serde::json::Value {
-        type Error = anyhow::Error;
-        fn try_into(self) -> Result<TurtSlot, Self::Error> {
-            let s = self.as_array().unwrap();
-            if s.len() == 0 {
-                return Err(anyhow::anyhow!("No item"));
+        /// Parses the response to `turtle.getItemDetail()`, which returns nothing for empty slots.
+        pub fn from_detail(resp: TurtResponse) -> Result<Option<TurtSlot>, TurtError> {
+            let v = resp.value()?;
+            let s = v.as_array().ok_or_else(|| malformed("Not an array"))?;
+            if s.is_empty() || s[0].is_null() {
+                return Ok(None);
             }
 
-            let o = s[0].as_object().unwrap();
-            Ok(TurtSlot {
-                count: o.get("count").unwrap().as_i64().unwrap() as i32,
-                name: o.get("name").unwrap().as_str().unwrap().to_string(),
-            })
-        }
-    }
-
-    impl TryFrom<TurtResponse> for TurtSlot {
-        type Error = anyhow::Error;
-        fn try_from(value: TurtResponse) -> Result<Self, Self::Error> {
-            match value {
-                TurtResponse::Ok(v) => v.try_into(),
-                TurtResponse::BadReq(e) => Err(anyhow::anyhow!(e)),
-                TurtResponse::BadCode(e) => Err(anyhow::anyhow!(e)),
-            }
+            let o = s[0].as_object().ok_or_else(|| malformed("Not an object"))?;
+            Ok(Some(TurtSlot {
+                count: o.get("count").and_then(|c| c.as_i64()).ok_or_else(|| malformed("No count"))? as i32,
+                name: o.get("name").and_then(|n| n.as_str()).ok_or_else(|| malformed("No name"))?.to_string(),
+            }))
         }
     }
 }
 
 mod world {
-    use super::TurtResponse;
+    use super::{malformed, TurtResponse};
+    use crate::turtle_core::error::TurtError;
+
+    /// What `TurtCommand::DigUnless` fails with, followed by the block that was left alone. Has to
+    /// match `protu.lua`.
+    pub const NEVER_DIG_MSG: &str = "Never dig: ";
 
     #[derive(serde::Deserialize, Debug)]
     pub struct TurtMovement {
@@ -98,24 +197,110 @@ mod world {
         pub fn msg(&self) -> &Option<String> {
             &self.msg
         }
+
+        /// Why the turtle couldn't move or dig, `None` if it could.
+        pub fn failure(&self) -> Option<MoveFailure> {
+            match self.success {
+                true => None,
+                false => Some(MoveFailure::from_msg(self.msg.as_deref())),
+            }
+        }
     }
 
-    impl TryInto<TurtMovement> for rocket::serde::json::Value {
-        type Error = anyhow::Error;
-        fn try_into(self) -> Result<TurtMovement, Self::Error> {
-            let vals = self.as_array().ok_or(anyhow::anyhow!("Not an array"))?;
+    /// Why a movement or dig failed, from the message the turtle returned.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum MoveFailure {
+        OutOfFuel,
+        /// Something is in the way, e.g. a mob, another turtle or falling gravel.
+        Obstructed,
+        /// E.g. bedrock.
+        Unbreakable,
+        /// Claimed by another player.
+        Protected,
+        /// Above the build limit, below the bottom of the world or past the world border.
+        WorldLimit,
+        NothingToDig,
+        /// The block is on the list of blocks that are never dug, see `NavConfig::never_digs`.
+        NeverDig(String),
+        /// Another turtle is there or is about to move there, see `Reservations`.
+        Reserved(crate::TurtleIdentifier),
+        Other(String),
+        /// The turtle didn't say.
+        Unknown,
+    }
+
+    impl MoveFailure {
+        pub fn from_msg(msg: Option<&str>) -> Self {
+            match msg {
+                Some("Out of fuel") => MoveFailure::OutOfFuel,
+                Some("Movement obstructed") => MoveFailure::Obstructed,
+                Some("Cannot break unbreakable block") => MoveFailure::Unbreakable,
+                Some("Cannot break protected block") | Some("Cannot enter protected area") => MoveFailure::Protected,
+                Some("Too high to move") | Some("Too low to move") | Some("Cannot pass the world border") => {
+                    MoveFailure::WorldLimit
+                }
+                Some("Nothing to dig here") => MoveFailure::NothingToDig,
+                Some(msg) => match msg.strip_prefix(NEVER_DIG_MSG) {
+                    Some(block) => MoveFailure::NeverDig(block.to_string()),
+                    None => MoveFailure::Other(msg.to_string()),
+                },
+                None => MoveFailure::Unknown,
+            }
+        }
+
+        /// Whether trying again can't help.
+        pub fn is_permanent(&self) -> bool {
+            matches!(
+                self,
+                MoveFailure::OutOfFuel
+                    | MoveFailure::Unbreakable
+                    | MoveFailure::Protected
+                    | MoveFailure::WorldLimit
+                    | MoveFailure::NeverDig(_)
+            )
+        }
+
+        pub fn into_error(self) -> TurtError {
+            match self {
+                MoveFailure::OutOfFuel => TurtError::OutOfFuel,
+                f => TurtError::MovementBlocked(f),
+            }
+        }
+    }
+
+    impl std::fmt::Display for MoveFailure {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                MoveFailure::OutOfFuel => write!(f, "out of fuel"),
+                MoveFailure::Obstructed => write!(f, "obstructed"),
+                MoveFailure::Unbreakable => write!(f, "unbreakable block"),
+                MoveFailure::Protected => write!(f, "protected area"),
+                MoveFailure::WorldLimit => write!(f, "edge of the world"),
+                MoveFailure::NothingToDig => write!(f, "nothing to dig"),
+                MoveFailure::NeverDig(block) => write!(f, "won't dig {}", block),
+                MoveFailure::Reserved(turtle) => write!(f, "turtle {} is in the way", turtle),
+                MoveFailure::Other(msg) => write!(f, "{}", msg),
+                MoveFailure::Unknown => write!(f, "unknown reason"),
+            }
+        }
+    }
+
+    impl TryFrom<rocket::serde::json::Value> for TurtMovement {
+        type Error = TurtError;
+        fn try_from(value: rocket::serde::json::Value) -> Result<Self, Self::Error> {
+            let vals = value.as_array().ok_or_else(|| malformed("Not an array"))?;
 
             if vals.len() < 1 || vals.len() > 2 {
-                return Err(anyhow::anyhow!("Invalid response."));
+                return Err(malformed("Invalid response."));
             }
 
-            let success = vals[0].as_bool().ok_or(anyhow::anyhow!("Not a bool"))?;
+            let success = vals[0].as_bool().ok_or_else(|| malformed("Not a bool"))?;
             if vals.len() == 1 {
                 Ok(TurtMovement { success, msg: None })
             } else {
                 let msg = vals[1]
                     .as_str()
-                    .ok_or(anyhow::anyhow!("No msg."))?
+                    .ok_or_else(|| malformed("No msg."))?
                     .to_string();
                 Ok(TurtMovement {
                     success,
@@ -126,60 +311,64 @@ mod world {
     }
 
     impl TryFrom<TurtResponse> for TurtMovement {
-        type Error = anyhow::Error;
+        type Error = TurtError;
         fn try_from(value: TurtResponse) -> Result<Self, Self::Error> {
-            match value {
-                TurtResponse::Ok(v) => v.try_into(),
-                TurtResponse::BadReq(e) => Err(anyhow::anyhow!(e)),
-                TurtResponse::BadCode(e) => Err(anyhow::anyhow!(e)),
-            }
+            value.value()?.try_into()
         }
     }
 
     #[derive(serde::Deserialize, Debug)]
     pub struct TurtInspect {
         block: Option<String>,
+        /// Tags of the block, e.g. `minecraft:logs`.
+        #[serde(default)]
+        tags: Vec<String>,
     }
 
     impl TurtInspect {
         pub fn block(&self) -> &Option<String> {
             &self.block
         }
+
+        pub fn tags(&self) -> &[String] {
+            &self.tags
+        }
     }
 
-    impl TryInto<TurtInspect> for rocket::serde::json::Value {
-        type Error = anyhow::Error;
-        fn try_into(self) -> Result<TurtInspect, Self::Error> {
-            let vals = self.as_array().ok_or(anyhow::anyhow!("Not an array"))?;
+    impl TryFrom<rocket::serde::json::Value> for TurtInspect {
+        type Error = TurtError;
+        fn try_from(value: rocket::serde::json::Value) -> Result<Self, Self::Error> {
+            let vals = value.as_array().ok_or_else(|| malformed("Not an array"))?;
 
             if vals.len() != 2 {
-                return Err(anyhow::anyhow!("Invalid response."));
+                return Err(malformed("Invalid response."));
             }
 
-            let block = vals[0].as_bool().ok_or(anyhow::anyhow!("Not a bool"))?;
+            let block = vals[0].as_bool().ok_or_else(|| malformed("Not a bool"))?;
             if !block {
-                Ok(TurtInspect { block: None })
+                Ok(TurtInspect { block: None, tags: vec![] })
             } else {
                 let name = vals[1]["name"]
                     .as_str()
-                    .ok_or(anyhow::anyhow!("No name."))?
+                    .ok_or_else(|| malformed("No name."))?
                     .to_string();
+                // A table of tag names to `true`, which is empty if the block has no tags
+                let tags = match vals[1]["tags"].as_object() {
+                    Some(tags) => tags.keys().cloned().collect(),
+                    None => vec![],
+                };
                 Ok(TurtInspect {
                     block: Some(name),
+                    tags,
                 })
             }
         }
     }
 
     impl TryFrom<TurtResponse> for TurtInspect {
-        type Error = anyhow::Error;
+        type Error = TurtError;
         fn try_from(value: TurtResponse) -> Result<Self, Self::Error> {
-            match value {
-                TurtResponse::Ok(v) => v.try_into(),
-                TurtResponse::BadReq(e) => Err(anyhow::anyhow!(e)),
-                TurtResponse::BadCode(e) => Err(anyhow::anyhow!(e)),
-            }
+            value.value()?.try_into()
         }
     }
 }
-
@@ -0,0 +1,74 @@
+//! Errors that can happen while talking to a turtle.
+use std::time::Duration;
+use super::data::MoveFailure;
+use super::navigation::Pos;
+
+#[derive(Debug, Clone)]
+pub enum TurtError {
+    /// The turtle registered again (or the server dropped it), so this worker can't reach it anymore.
+    Disconnected,
+    /// The turtle didn't answer in time, e.g. because its chunk was unloaded.
+    Timeout { cmd: String, after: Duration },
+    /// The turtle couldn't load the command.
+    BadCode(String),
+    /// The turtle couldn't execute the command.
+    BadReq(String),
+    /// The turtle answered with something we didn't expect.
+    MalformedResponse(String),
+    /// `gps.locate()` didn't return a position.
+    NoGps,
+    /// Slots are 0-indexed and turtles have 16 of them.
+    InvalidSlot(u8),
+    /// The turtle couldn't move, or couldn't dig its way through.
+    MovementBlocked(MoveFailure),
+    /// The turtle can't move until it is refueled.
+    OutOfFuel,
+    /// There is no empty slot to take items into.
+    InventoryFull,
+    /// No route to the position that doesn't go through blocks that can't be dug.
+    NoPath(Pos),
+    /// The turtle's position or progress couldn't be saved.
+    Storage(String),
+    /// The fuel depot has an item that can't be burnt. It was put back.
+    NotFuel(String),
+    /// The job can't be run as it is configured, e.g. because its model can't be loaded.
+    BadJob(String),
+}
+
+impl TurtError {
+    /// Whether the turtle can't be talked to, as opposed to a single command failing.
+    pub fn is_connection(&self) -> bool {
+        matches!(self, TurtError::Disconnected | TurtError::Timeout { .. })
+    }
+}
+
+impl std::fmt::Display for TurtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TurtError::Disconnected => write!(f, "Turtle has disconnected"),
+            TurtError::Timeout { cmd, after } => {
+                write!(f, "No response to `{}` after {:?}", cmd, after)
+            }
+            TurtError::BadCode(e) => write!(f, "Bad code: {}", e),
+            TurtError::BadReq(e) => write!(f, "Bad request: {}", e),
+            TurtError::MalformedResponse(e) => write!(f, "Malformed response: {}", e),
+            TurtError::NoGps => write!(f, "No GPS here"),
+            TurtError::InvalidSlot(slot) => write!(f, "Invalid slot number: {}", slot),
+            TurtError::MovementBlocked(reason) => write!(f, "Movement blocked: {}", reason),
+            TurtError::OutOfFuel => write!(f, "Out of fuel"),
+            TurtError::InventoryFull => write!(f, "Inventory is full"),
+            TurtError::NoPath(dst) => write!(f, "No path to {}", dst),
+            TurtError::Storage(e) => write!(f, "Couldn't save: {}", e),
+            TurtError::NotFuel(item) => write!(f, "Fuel depot has {}, which can't be burnt", item),
+            TurtError::BadJob(e) => write!(f, "Bad job: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for TurtError {
+    fn from(e: std::io::Error) -> Self {
+        TurtError::Storage(e.to_string())
+    }
+}
+
+impl std::error::Error for TurtError {}
@@ -0,0 +1,128 @@
+//! Structured events about what the turtles are doing. Every event goes through the `EventBus`,
+//! which the web server exposes as a stream and which is also logged to the terminal.
+use rocket::tokio::sync::broadcast;
+use serde::Serialize;
+use crate::jobs::JobId;
+use crate::TurtleIdentifier;
+use super::navigation::{Pos, PosH};
+use super::status::{now_millis, TurtStatus};
+
+/// Number of events a slow subscriber can fall behind by before it starts missing them.
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TurtEventKind {
+    Registered,
+    JobStarted { job: JobId },
+    JobFinished { job: JobId },
+    Moved,
+    Dug,
+    Placed,
+    InventoryRefill,
+    WaitingForChest,
+    /// On the way to the fuel depot.
+    Refueling,
+    WaitingForFuel,
+    /// There is no GPS, the turtle goes by its saved position.
+    DeadReckoning,
+    /// GPS put the turtle somewhere else than where it was thought to be.
+    Drift { tracked: PosH, actual: Pos },
+    /// A new turtle that was boxed in dug through `block`, which is gone now, to find out which way
+    /// it faces.
+    DugToLocate { block: Option<String> },
+    Disconnected,
+    /// The turtle stopped answering commands.
+    Unreachable,
+    /// The turtle is answering commands again.
+    Reachable,
+    Error { msg: String },
+}
+
+impl std::fmt::Display for TurtEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TurtEventKind::Registered => write!(f, "registered"),
+            TurtEventKind::JobStarted { job } => write!(f, "started job {}", job),
+            TurtEventKind::JobFinished { job } => write!(f, "finished job {}", job),
+            TurtEventKind::Moved => write!(f, "moved"),
+            TurtEventKind::Dug => write!(f, "dug"),
+            TurtEventKind::Placed => write!(f, "placed"),
+            TurtEventKind::InventoryRefill => write!(f, "is refilling its inventory"),
+            TurtEventKind::WaitingForChest => write!(f, "is waiting for the chest to refill"),
+            TurtEventKind::Refueling => write!(f, "is going to refuel"),
+            TurtEventKind::WaitingForFuel => write!(f, "is waiting for the fuel chest to refill"),
+            TurtEventKind::DeadReckoning => write!(f, "has no GPS, going by its saved position"),
+            TurtEventKind::Drift { tracked, actual } => write!(f, "drifted, it is at {} rather than {}", actual, tracked),
+            TurtEventKind::DugToLocate { block: Some(block) } => {
+                write!(f, "was boxed in and dug through {} to find its heading", block)
+            }
+            TurtEventKind::DugToLocate { block: None } => write!(f, "was boxed in and moved to find its heading"),
+            TurtEventKind::Disconnected => write!(f, "disconnected"),
+            TurtEventKind::Unreachable => write!(f, "is unreachable"),
+            TurtEventKind::Reachable => write!(f, "is reachable again"),
+            TurtEventKind::Error { msg } => write!(f, "error: {}", msg),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TurtEvent {
+    pub turtle: TurtleIdentifier,
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub pos: Option<PosH>,
+    /// The last command sent to the turtle.
+    pub cmd: Option<String>,
+    #[serde(flatten)]
+    pub kind: TurtEventKind,
+}
+
+impl TurtEvent {
+    pub fn new(status: &TurtStatus, kind: TurtEventKind) -> Self {
+        Self {
+            turtle: status.id,
+            timestamp: now_millis(),
+            pos: status.pos.clone(),
+            cmd: status.last_cmd.clone(),
+            kind,
+        }
+    }
+}
+
+impl std::fmt::Display for TurtEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Turtle {} {}", self.turtle, self.kind)?;
+        if let Some(pos) = &self.pos {
+            write!(f, " {}", pos)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<TurtEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { tx }
+    }
+
+    pub fn emit(&self, event: TurtEvent) {
+        // Nobody might be listening, which is fine
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TurtEvent> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
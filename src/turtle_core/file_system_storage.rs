@@ -4,20 +4,54 @@ pub trait FStore {
     fn default(p: &std::path::PathBuf) -> Self;
     fn path(&self) -> &std::path::PathBuf;
     fn save(&self) -> String;
-    fn load(p: &std::path::PathBuf, d: &str) -> Self;
+    /// `None` if `d` isn't what `save` writes, e.g. because the file was cut short.
+    fn load(p: &std::path::PathBuf, d: &str) -> Option<Self> where Self: Sized;
 }
 
-pub fn fstore_save<T: FStore>(t: &T) {
-    let mut f = std::fs::File::create(t.path()).unwrap();
-    f.write_all(t.save().as_bytes()).unwrap();
+/// Writes to a temporary file that then replaces the old one, so a crash can't leave a file that is
+/// only half written. Once this returns, the new file survives a crash as well.
+pub fn write_atomically(p: &std::path::Path, d: &[u8]) -> std::io::Result<()> {
+    let mut tmp = p.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut f = std::fs::File::create(&tmp)?;
+    f.write_all(d)?;
+    f.sync_all()?;
+    std::fs::rename(&tmp, p)?;
+    // The rename is only written down with the directory
+    let dir = match p.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    std::fs::File::open(dir)?.sync_all()
 }
 
-pub fn fstore_load_or_init<T: FStore>(p: &std::path::PathBuf) -> T {
-    if !p.exists() {
-        let t = T::default(p);
-        fstore_save(&t);
-        t
-    } else {
-        T::load(p, std::fs::read_to_string(p).unwrap().as_str())
+pub fn fstore_save<T: FStore>(t: &T) -> std::io::Result<()> {
+    write_atomically(t.path(), t.save().as_bytes())
+}
+
+/// `None` if there is no file at `p` or it can't be read.
+pub fn fstore_load<T: FStore>(p: &std::path::PathBuf) -> Option<T> {
+    let d = std::fs::read_to_string(p).ok()?;
+    let t = T::load(p, d.as_str());
+    if t.is_none() {
+        println!("Couldn't read {}, ignoring it", p.display());
     }
+    t
+}
+
+/// Starts over with the default if the file is missing or can't be read.
+pub fn fstore_load_or_init<T: FStore>(p: &std::path::PathBuf) -> std::io::Result<T> {
+    match fstore_load(p) {
+        Some(t) => Ok(t),
+        None => {
+            let t = T::default(p);
+            fstore_save(&t)?;
+            Ok(t)
+        }
+    }
+}
+
+pub fn fstore_remove<T: FStore>(t: &T) {
+    // It might not have been saved in the first place
+    let _ = std::fs::remove_file(t.path());
 }
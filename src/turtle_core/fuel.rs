@@ -0,0 +1,101 @@
+//! Keeps turtles from running out of fuel halfway through a job. `TurtNavigation` estimates what
+//! each route costs and sends the turtle to a fuel depot before it runs low.
+use rocket::tokio::time::{self, Duration};
+
+use super::control::TurtControl;
+use super::data::TurtFuel;
+use super::error::TurtError;
+use super::events::TurtEventKind;
+use super::inventory::TURT_SLOTS;
+use super::navigation::{Pos, PosH};
+
+/// Read from the `fuel` table of a job.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct FuelConfig {
+    /// Where the turtle goes to refuel. The chest with the fuel is below it.
+    pub depot: PosH,
+    /// Fuel that is kept on top of what the turtle needs to get where it's going and back to the
+    /// depot.
+    #[serde(default = "FuelConfig::default_threshold")]
+    pub threshold: u32,
+    /// How much fuel the turtle tops up to at the depot, at most its fuel limit.
+    #[serde(default = "FuelConfig::default_refuel_to")]
+    pub refuel_to: u32,
+}
+
+impl FuelConfig {
+    fn default_threshold() -> u32 {
+        100
+    }
+
+    fn default_refuel_to() -> u32 {
+        5_000
+    }
+}
+
+/// How much fuel it takes to go from `a` to `b`, which `goto_nohead` does one axis at a time.
+pub fn route_cost(a: &Pos, b: &Pos) -> u32 {
+    (a.x.abs_diff(b.x) + a.y.abs_diff(b.y) + a.z.abs_diff(b.z)) as u32
+}
+
+/// Burns items from the chest below until the turtle has at least `target` fuel, or as much as it
+/// can hold. Only as many items are burnt as it takes, the rest go back. Waits for the chest to be
+/// refilled if it runs out, and fails on the first item that can't be burnt. Returns the new fuel
+/// level.
+pub async fn refuel_from_chest(turt: &TurtControl, target: u32) -> Result<TurtFuel, TurtError> {
+    let limit = match turt.fuel_limit().await? {
+        TurtFuel::Unlimited => return Ok(TurtFuel::Unlimited),
+        TurtFuel::Level(limit) => limit,
+    };
+    let target = target.min(limit);
+
+    // Scripts keep track of the selected slot themselves, so it is put back afterwards
+    let selected = turt.inv_selected_slot().await?;
+    let mut slot = None;
+    for s in 0..TURT_SLOTS as u8 {
+        if turt.inv_item_count(s).await? == 0 {
+            slot = Some(s);
+            break;
+        }
+    }
+    let slot = slot.ok_or(TurtError::InventoryFull)?;
+    turt.inv_select(slot).await?;
+
+    loop {
+        let fuel = turt.fuel_level().await?;
+        if fuel.enough_for(target) {
+            // Whatever wasn't burnt goes back, the slot might be needed
+            if turt.inv_item_count(slot).await? > 0 {
+                turt.inv_drop_down().await?;
+            }
+            turt.inv_select(selected).await?;
+            return Ok(fuel);
+        }
+
+        let TurtFuel::Level(before) = fuel else {
+            unreachable!("unlimited fuel is always enough")
+        };
+
+        if turt.inv_item_count(slot).await? == 0 && !turt.suck_down().await?.success() {
+            turt.emit(TurtEventKind::WaitingForFuel);
+            time::sleep(Duration::from_millis(10000)).await;
+            continue;
+        }
+        // One item first, to find out how much fuel each one is worth
+        if !turt.refuel(Some(1)).await?.success() {
+            let item = turt.inv_item_detail(slot).await?.map(|s| s.name().to_string()).unwrap_or_default();
+            turt.inv_drop_down().await?;
+            turt.inv_select(selected).await?;
+            return Err(TurtError::NotFuel(item));
+        }
+        let after = match turt.fuel_level().await? {
+            TurtFuel::Level(n) => n,
+            TurtFuel::Unlimited => continue,
+        };
+        let per_item = after.saturating_sub(before).max(1);
+        let count = target.saturating_sub(after).div_ceil(per_item).min(u8::MAX as u32) as u8;
+        if count > 0 {
+            turt.refuel(Some(count)).await?;
+        }
+    }
+}
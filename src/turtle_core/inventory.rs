@@ -1,9 +1,11 @@
 use super::data::TurtSlot;
 use super::control::TurtControl;
+use super::error::TurtError;
 
 pub const TURT_SLOTS: usize = 16;
 
-#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum TurtBlock {
     None,
     Any,
@@ -12,22 +14,24 @@ pub enum TurtBlock {
 
 #[derive(Debug)]
 pub struct TurtInventory<'a> {
-    turt: &'a TurtControl<'a>,
+    turt: &'a TurtControl,
     pub slots: Vec<Option<TurtSlot>>,
 }
 
 impl<'a> TurtInventory<'a> {
-    pub fn init(turt: &'a TurtControl<'a>) -> Self {
+    pub fn init(turt: &'a TurtControl) -> Self {
         Self {
             turt,
             slots: vec![None; TURT_SLOTS],
         }
     }
 
-    pub fn full_update(&mut self) {
+    pub async fn full_update(&mut self) -> Result<(), TurtError> {
         for s in 0..TURT_SLOTS {
-            self.slots[s] = self.turt.inv_item_detail(s as u8)
+            self.slots[s] = self.turt.inv_item_detail(s as u8).await?;
         }
+        self.turt.update_status(|s| s.inventory = self.slots.clone());
+        Ok(())
     }
 
     pub fn is_full(&self) -> bool {
@@ -39,14 +43,14 @@ impl<'a> TurtInventory<'a> {
         true
     }
 
-    pub fn reduce_count_andor_find_next(&mut self, start_slot: usize) -> Option<i32> {
+    pub async fn reduce_count_andor_find_next(&mut self, start_slot: usize) -> Result<Option<i32>, TurtError> {
         let mut slot = start_slot;
         let mut ignore = false;
         'redo: loop {
             if let Some(s) = &mut self.slots[slot] {
                 if s.count() > 0 {
                     s.reduce_count(1);
-                    return Some(slot as i32);
+                    return Ok(Some(slot as i32));
                 } else if s.count() == 0 {
                     if !ignore {
                         self.slots[slot] = None;
@@ -57,7 +61,7 @@ impl<'a> TurtInventory<'a> {
             } else {
                 if !ignore {
                     ignore = true;
-                    self.slots[slot] = self.turt.inv_item_detail(slot as u8);
+                    self.slots[slot] = self.turt.inv_item_detail(slot as u8).await?;
                     continue 'redo;
                 }
             };
@@ -67,7 +71,7 @@ impl<'a> TurtInventory<'a> {
                 slot = 0;
             }
             if slot == start_slot {
-                return None;
+                return Ok(None);
             }
         }
     }
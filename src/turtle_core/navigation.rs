@@ -3,22 +3,30 @@ pub use heading::Head;
 pub use position::{Pos, PosH};
 
 // Crate imports
-use super::control::{TurtControl, TurtFunc};
-use super::data::{TurtResponse, TurtInspect};
+use super::command::TurtCommand;
+use super::control::{TurtControl, TurtDir};
+use super::data::{MoveFailure, TurtFuel, TurtInspect, TurtMovement, TurtResponse};
+use super::fuel::{self, FuelConfig};
+use super::pathfinding::{self, is_turtle, NavConfig};
+use super::reservations::Claim;
+use super::world_map::Cell;
 
 // External imports
 use modelutils_rs::coords::{Axis, Order};
-use rand::Rng;
+use rocket::tokio::time::{self, Duration};
 use std::path::PathBuf;
-use std::sync::mpsc;
-use crate::turtle_core::data::TurtMovement;
-use crate::turtle_core::file_system_storage::{FStore, fstore_load_or_init, fstore_save};
-use crate::TurtleIdentifier;
+use crate::turtle_core::error::TurtError;
+use crate::turtle_core::events::TurtEventKind;
+use crate::turtle_core::file_system_storage::{FStore, fstore_load, fstore_remove, fstore_save};
+use crate::{data_path, TurtleIdentifier};
 
 pub const NAV_DIR: &str = "positions";
+/// How long to wait before trying again to get into a block another turtle is in.
+const RESERVATION_WAIT: Duration = Duration::from_millis(500);
 
 mod heading {
-    #[derive(Debug, Clone)]
+    #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+    #[serde(rename_all = "lowercase")]
     pub enum Head {
         N,
         E,
@@ -78,10 +86,11 @@ mod heading {
 }
 
 mod position {
-    use crate::turtle_core::data::TurtResponse;
+    use crate::turtle_core::control::TurtDir;
+    use crate::turtle_core::error::TurtError;
     use super::heading::Head;
 
-    #[derive(Debug, Clone)]
+    #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
     pub struct Pos {
         pub x: i64,
         pub y: i64,
@@ -112,7 +121,7 @@ mod position {
         }
     }
 
-    #[derive(Debug, Clone)]
+    #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
     pub struct PosH {
         pub x: i64,
         pub y: i64,
@@ -131,6 +140,26 @@ mod position {
         }
     }
 
+    impl PosH {
+        pub fn with_head(self, h: Head) -> Self {
+            Self { h, ..self }
+        }
+
+        /// The block in front, above or below.
+        pub fn neighbour(&self, dir: TurtDir) -> Pos {
+            match dir {
+                TurtDir::Forw => match self.h {
+                    Head::N => Pos::new(self.x, self.y, self.z - 1),
+                    Head::E => Pos::new(self.x + 1, self.y, self.z),
+                    Head::S => Pos::new(self.x, self.y, self.z + 1),
+                    Head::W => Pos::new(self.x - 1, self.y, self.z),
+                },
+                TurtDir::Up => Pos::new(self.x, self.y + 1, self.z),
+                TurtDir::Down => Pos::new(self.x, self.y - 1, self.z),
+            }
+        }
+    }
+
     impl Default for PosH {
         fn default() -> Self {
             Self {
@@ -142,15 +171,25 @@ mod position {
         }
     }
 
-    impl Into<PosH> for rocket::serde::json::Value {
-        fn into(self) -> PosH {
-            let p = self.as_array().unwrap();
-            PosH {
-                x: p[0].as_i64().unwrap(),
-                y: p[1].as_i64().unwrap(),
-                z: p[2].as_i64().unwrap(),
-                h: Head::N,
+    /// From the response to `gps.locate()`, which returns nothing if there is no GPS in range.
+    impl TryFrom<rocket::serde::json::Value> for PosH {
+        type Error = TurtError;
+        fn try_from(value: rocket::serde::json::Value) -> Result<Self, Self::Error> {
+            let p = value
+                .as_array()
+                .ok_or_else(|| TurtError::MalformedResponse("Not an array".to_string()))?;
+            if p.is_empty() || p[0].is_null() {
+                return Err(TurtError::NoGps);
             }
+            let coord = |i: usize| p.get(i)
+                .and_then(|c| c.as_i64())
+                .ok_or_else(|| TurtError::MalformedResponse(format!("Not a position: {:?}", p)));
+            Ok(PosH {
+                x: coord(0)?,
+                y: coord(1)?,
+                z: coord(2)?,
+                h: Head::N,
+            })
         }
     }
 }
@@ -161,6 +200,19 @@ struct FStoreNav {
     fp: PathBuf,
 }
 
+fn posh_lines(p: &PosH) -> String {
+    format!("{}\n{}\n{}\n{}\n", p.x, p.y, p.z, p.h.to_string())
+}
+
+fn posh_from_lines(lines: &[&str]) -> Option<PosH> {
+    Some(PosH {
+        x: lines.first()?.parse::<i64>().ok()?,
+        y: lines.get(1)?.parse::<i64>().ok()?,
+        z: lines.get(2)?.parse::<i64>().ok()?,
+        h: Head::from_str(lines.get(3)?).ok()?,
+    })
+}
+
 impl FStore for FStoreNav {
     fn default(p: &PathBuf) -> Self {
         Self {
@@ -174,36 +226,103 @@ impl FStore for FStoreNav {
     }
 
     fn save(&self) -> String {
-        format!(
-            "{}\n{}\n{}\n{}\n",
-            self.p.x,
-            self.p.y,
-            self.p.z,
-            self.p.h.to_string()
-        )
+        posh_lines(&self.p)
     }
 
-    fn load(p: &PathBuf, d: &str) -> Self {
-        let lines: Vec<String> = d.lines().map(String::from).collect();
+    fn load(p: &PathBuf, d: &str) -> Option<Self> {
+        let lines: Vec<&str> = d.lines().collect();
+        Some(Self {
+            p: posh_from_lines(&lines)?,
+            fp: p.clone(),
+        })
+    }
+}
+
+/// The move or turn a turtle is about to make. Written before the command is sent and removed once
+/// the position it leads to is saved, so if it is still there on startup the server stopped while
+/// the turtle was moving.
+#[derive(Debug)]
+struct FStoreIntent {
+    from: PosH,
+    to: PosH,
+    /// The fuel level before moving, if it was known.
+    fuel: Option<u32>,
+    fp: PathBuf,
+}
+
+impl FStore for FStoreIntent {
+    fn default(p: &PathBuf) -> Self {
         Self {
-            p: PosH {
-                x: lines[0].parse::<i64>().unwrap(),
-                y: lines[1].parse::<i64>().unwrap(),
-                z: lines[2].parse::<i64>().unwrap(),
-                h: Head::from_str(lines[3].as_str()).unwrap(),
-            },
+            from: PosH::default(),
+            to: PosH::default(),
+            fuel: None,
             fp: p.clone(),
         }
     }
+
+    fn path(&self) -> &PathBuf {
+        &self.fp
+    }
+
+    fn save(&self) -> String {
+        let fuel = self.fuel.map(|f| f.to_string()).unwrap_or_default();
+        format!("{}{}{}\n", posh_lines(&self.from), posh_lines(&self.to), fuel)
+    }
+
+    fn load(p: &PathBuf, d: &str) -> Option<Self> {
+        let lines: Vec<&str> = d.lines().collect();
+        Some(Self {
+            from: posh_from_lines(&lines)?,
+            to: posh_from_lines(lines.get(4..)?)?,
+            fuel: lines.get(8).and_then(|f| f.parse::<u32>().ok()),
+            fp: p.clone(),
+        })
+    }
+}
+
+fn same_block(a: &PosH, b: &PosH) -> bool {
+    (a.x, a.y, a.z) == (b.x, b.y, b.z)
+}
+
+fn intent_path(turtleid: TurtleIdentifier) -> PathBuf {
+    data_path(NAV_DIR).join(format!("{}.intent", turtleid))
+}
+
+/// Which way a turtle that moved forward from `p1` to `p2` is facing.
+fn heading_between(p1: &PosH, p2: &PosH) -> Head {
+    if p2.z < p1.z {
+        Head::N
+    } else if p2.z > p1.z {
+        Head::S
+    } else if p2.x < p1.x {
+        Head::W
+    } else {
+        Head::E
+    }
 }
 
 #[derive(Debug)]
 pub struct TurtNavigation<'a> {
-    turt: &'a TurtControl<'a>,
+    turtleid: TurtleIdentifier,
+    turt: &'a TurtControl,
+    /// Claim blocks before moving into them and wait for other turtles, see `Reservations`.
     avoid_other_turtles: bool,
-    next_tx: mpsc::Sender<String>,
-    cmdcomplete_rx: &'a mpsc::Receiver<TurtResponse>,
+    /// False while `gps_init` is finding out where the turtle is.
+    located: bool,
+    /// Whether the position was read from a file or located, rather than made up for a new turtle.
+    saved: bool,
     fstore_nav: FStoreNav,
+    /// Counted down as the turtle moves, so it doesn't have to be asked every time.
+    fuel: Option<TurtFuel>,
+    fuel_conf: Option<FuelConfig>,
+    /// Set while going to the depot, which mustn't start another trip there.
+    refueling: bool,
+    nav_conf: NavConfig,
+    /// Moves since the position was last checked with GPS.
+    moves_unverified: usize,
+    /// The move or turn that is under way or, right after startup, the one that was under way when
+    /// the server stopped. See `FStoreIntent`.
+    intent: Option<FStoreIntent>,
 }
 
 impl std::fmt::Display for PosH {
@@ -226,256 +345,708 @@ impl<'a> std::fmt::Display for TurtNavigation<'a> {
 }
 
 impl<'a> TurtNavigation<'a> {
+    /// Picks up the saved position, if there is one. Nothing is written until `gps_init`, which has
+    /// to be called before the turtle is moved: only it finds out whether a move that was under way
+    /// when the server stopped went through.
     pub fn new(
         turtleid: TurtleIdentifier,
         turt: &'a TurtControl,
         avoid_other_turtles: bool,
-        next_tx: mpsc::Sender<String>,
-        cmdcomplete_rx: &'a mpsc::Receiver<TurtResponse>,
     ) -> Self {
-        let fp = std::path::PathBuf::from(
-            format!("{}/{}.nav", NAV_DIR, turtleid));
-        let fstore_nav = fstore_load_or_init(&fp);
+        let fp = data_path(NAV_DIR).join(format!("{}.nav", turtleid));
+        let (fstore_nav, intent) = match (fstore_load::<FStoreNav>(&fp), fstore_load::<FStoreIntent>(&intent_path(turtleid))) {
+            // The position it led to was saved already
+            (Some(nav), Some(i)) if same_block(&nav.p, &i.to) && nav.p.h.diff(&i.to.h) == 0 => {
+                fstore_remove(&i);
+                (Some(nav), None)
+            }
+            // Saving the position never got that far, it was where the move or turn started
+            (None, Some(i)) => (Some(FStoreNav { p: i.from.clone(), fp: fp.clone() }), Some(i)),
+            (nav, i) => (nav, i),
+        };
+        let saved = fstore_nav.is_some();
+        let fstore_nav = fstore_nav.unwrap_or_else(|| FStoreNav::default(&fp));
+        turt.update_status(|s| s.pos = Some(fstore_nav.p.clone()));
 
         Self {
+            turtleid,
             turt,
             avoid_other_turtles,
-            next_tx,
-            cmdcomplete_rx,
+            located: true,
+            saved,
             fstore_nav,
+            fuel: None,
+            fuel_conf: None,
+            refueling: false,
+            nav_conf: NavConfig::default(),
+            moves_unverified: 0,
+            intent,
         }
     }
 
+    pub fn set_nav_config(&mut self, conf: NavConfig) {
+        self.nav_conf = conf;
+    }
 
-    fn make_req(&self, cmd: &str) -> TurtResponse {
-        self.next_tx.send(cmd.to_string()).unwrap();
-        self.cmdcomplete_rx.recv().unwrap()
+    /// Without a fuel depot the turtle only stops once it is out of fuel.
+    pub fn set_fuel_config(&mut self, conf: Option<FuelConfig>) {
+        self.fuel_conf = conf;
+    }
+
+
+    async fn make_req(&self, cmd: TurtCommand) -> Result<TurtResponse, TurtError> {
+        self.turt.make_req_retry(cmd).await
     }
 
     pub fn pos(&self) -> &PosH {
         &self.fstore_nav.p
     }
 
-    pub fn pos_save(&self) {
+    pub fn pos_save(&self) -> Result<(), TurtError> {
         // I use this seemingly pointless helper function to be able to find usages using the lsp
-        fstore_save(&self.fstore_nav);
+        fstore_save(&self.fstore_nav)?;
+        self.turt.update_status(|s| s.pos = Some(self.fstore_nav.p.clone()));
+        if self.located {
+            self.turt.reservations().lock().unwrap().occupy(self.turtleid, &self.pos().into());
+        }
+        Ok(())
+    }
+
+    /// Saves the position after the turtle has moved to another block.
+    fn pos_moved(&mut self) -> Result<(), TurtError> {
+        self.moves_unverified += 1;
+        if let Some(TurtFuel::Level(n)) = self.fuel {
+            self.set_fuel(TurtFuel::Level(n.saturating_sub(1)));
+        }
+        self.pos_save()?;
+        self.intent_done();
+        self.turt.emit(TurtEventKind::Moved);
+        Ok(())
+    }
+
+    fn set_fuel(&mut self, fuel: TurtFuel) {
+        self.fuel = Some(fuel);
+        self.turt.update_status(|s| s.fuel = Some(fuel));
+    }
+
+    pub async fn fuel_level(&mut self) -> Result<TurtFuel, TurtError> {
+        if let Some(fuel) = self.fuel {
+            return Ok(fuel);
+        }
+        let fuel = self.turt.fuel_level().await?;
+        self.set_fuel(fuel);
+        Ok(fuel)
     }
 
-    pub fn gps_init(&mut self) {
-        let p1: PosH = match self.make_req("gps.locate()") {
-            TurtResponse::Ok(v) => v.into(),
-            _ => panic!("Oh oh... no gps here."),
+    /// Fuel needed to get to `dst` with `goto_nohead`, not counting detours.
+    pub fn route_cost(&self, dst: &Pos) -> u32 {
+        fuel::route_cost(&self.pos().into(), dst)
+    }
+
+    /// Goes to the fuel depot first if the turtle couldn't make it to `dst` and from there back to
+    /// the depot.
+    async fn ensure_fuel(&mut self, dst: &Pos) -> Result<(), TurtError> {
+        let conf = match &self.fuel_conf {
+            Some(conf) if !self.refueling => conf.clone(),
+            _ => return Ok(()),
         };
+        let needed = self.route_cost(dst) + fuel::route_cost(dst, &(&conf.depot).into()) + conf.threshold;
+        if self.fuel_level().await?.enough_for(needed) {
+            return Ok(());
+        }
 
-        self.mv_forw();
+        self.turt.emit(TurtEventKind::Refueling);
+        let saved_pos = self.pos().clone();
+        // Enough to get back here and then on to `dst`
+        let target = conf.refuel_to.max(needed + fuel::route_cost(&(&conf.depot).into(), &(&saved_pos).into()));
+        self.refueling = true;
+        let r = self.refuel_trip(&conf.depot, &saved_pos, target).await;
+        self.refueling = false;
+        r
+    }
+
+    async fn refuel_trip(&mut self, depot: &PosH, saved_pos: &PosH, target: u32) -> Result<(), TurtError> {
+        self.goto_head(depot, Order::XYZ).await?;
+        let fuel = fuel::refuel_from_chest(self.turt, target).await?;
+        self.set_fuel(fuel);
+        self.goto_head(saved_pos, Order::XYZ).await
+    }
 
-        self.fstore_nav.p = match self.make_req("gps.locate()") {
-            TurtResponse::Ok(v) => v.into(),
-            _ => panic!("This is bad..."),
+    async fn gps_locate(&self) -> Result<PosH, TurtError> {
+        self.make_req(TurtCommand::GpsLocate).await?.value()?.try_into()
+    }
+
+    /// Finds out where the turtle is and which way it is facing, without digging if it can help it.
+    ///
+    /// If the turtle is where it was saved, the saved heading is trusted. Otherwise the turtle steps
+    /// into a free block next to it and back, trying one block up and one block down if it's boxed
+    /// in. Only if that fails as well, it goes by the saved heading or, for a new turtle, digs its
+    /// way forward. Without GPS the saved position is trusted as is.
+    pub async fn gps_init(&mut self) -> Result<(), TurtError> {
+        // The saved position might be wrong, nothing is put on the map or reserved until it's known
+        self.turt.update_status(|s| s.pos = None);
+        self.located = false;
+        let gps = match self.gps_locate().await {
+            Ok(p) => Some(p),
+            Err(TurtError::NoGps) if self.saved => None,
+            Err(e) => return Err(e),
+        };
+        let heading_known = match self.intent.take() {
+            Some(intent) => {
+                let known = self.recover(&intent, gps.as_ref()).await?;
+                fstore_remove(&intent);
+                known
+            }
+            None => true,
+        };
+        let p1 = match gps {
+            Some(p) => p,
+            None => {
+                self.turt.emit(TurtEventKind::DeadReckoning);
+                self.located = true;
+                return self.pos_save();
+            }
         };
 
-        self.fstore_nav.p.h = if self.fstore_nav.p.z < p1.z {
-            Head::N
-        } else if self.fstore_nav.p.z > p1.z {
-            Head::S
-        } else if self.fstore_nav.p.x < p1.x {
-            Head::W
+        let p = if heading_known && self.saved && same_block(&self.fstore_nav.p, &p1) {
+            self.fstore_nav.p.clone()
         } else {
-            Head::E
+            self.find_heading(&p1).await?
+        };
+
+        self.fstore_nav.p = p;
+        self.located = true;
+        self.saved = true;
+        self.pos_save()
+    }
+
+    /// Works out whether the move in `intent`, which was under way when the server stopped, went
+    /// through. Goes by where GPS puts the turtle or, without GPS, by whether it used up fuel.
+    /// Returns whether the heading can be trusted, which it can't after a turn that may or may not
+    /// have happened.
+    async fn recover(&mut self, intent: &FStoreIntent, gps: Option<&PosH>) -> Result<bool, TurtError> {
+        self.fstore_nav.p = intent.from.clone();
+        if same_block(&intent.from, &intent.to) {
+            return Ok(false);
+        }
+        let moved = match (gps, intent.fuel) {
+            (Some(p), _) => same_block(p, &intent.to),
+            (None, Some(before)) => matches!(self.turt.fuel_level().await?, TurtFuel::Level(now) if now < before),
+            (None, None) => false,
+        };
+        if moved {
+            self.fstore_nav.p = intent.to.clone();
+        }
+        Ok(true)
+    }
+
+    /// Writes down that the turtle is about to move or turn to `to`, see `FStoreIntent`. Nothing is
+    /// written while `gps_init` is finding out where the turtle is.
+    fn intend(&mut self, to: PosH) -> Result<(), TurtError> {
+        if !self.located {
+            return Ok(());
+        }
+        let intent = FStoreIntent {
+            from: self.fstore_nav.p.clone(),
+            to,
+            fuel: match self.fuel {
+                Some(TurtFuel::Level(n)) => Some(n),
+                _ => None,
+            },
+            fp: intent_path(self.turtleid),
+        };
+        fstore_save(&intent)?;
+        self.intent = Some(intent);
+        Ok(())
+    }
+
+    /// The move or turn either failed or its position was saved.
+    fn intent_done(&mut self) {
+        if let Some(intent) = self.intent.take() {
+            fstore_remove(&intent);
+        }
+    }
+
+    /// Which way the turtle faces, with GPS putting it at `p1`, and where it ends up finding out.
+    /// Goes by the saved heading if the turtle is boxed in or, for a new turtle, digs its way forward
+    /// unless the block must never be dug, and emits `DugToLocate`.
+    async fn find_heading(&mut self, p1: &PosH) -> Result<PosH, TurtError> {
+        // Probing moves the turtle without counting down the fuel, so it is asked for again
+        self.fuel = None;
+        Ok(match self.probe_heading(p1).await? {
+            Some(p) => p,
+            None if self.saved => self.gps_locate().await?.with_head(self.fstore_nav.p.h.clone()),
+            // Boxed in, with nothing to go by. Nothing is saved until the heading is known, the
+            // position is only made up.
+            None => {
+                let p1 = self.gps_locate().await?;
+                let inspect = self.turt.insp_forw().await?;
+                if let Some(b) = inspect.block() {
+                    if self.nav_conf.never_digs(b, inspect.tags()) {
+                        return Err(TurtError::MovementBlocked(MoveFailure::NeverDig(b.clone())));
+                    }
+                    if let Some(f) = self.turt.dig_forw().await?.failure() {
+                        return Err(self.move_failed(f));
+                    }
+                }
+                self.turt.emit(TurtEventKind::DugToLocate { block: inspect.block().clone() });
+                if let Some(f) = self.turt.mv_forw().await?.failure() {
+                    return Err(self.move_failed(f));
+                }
+                let p2 = self.gps_locate().await?;
+                let h = heading_between(&p1, &p2);
+                p2.with_head(h)
+            }
+        })
+    }
+
+    /// Checks the tracked position against GPS. If the turtle isn't where it was thought to be, e.g.
+    /// after a move that went through without the server hearing of it, the position is corrected,
+    /// the heading found again and a `Drift` event emitted. Returns whether it had drifted. Without
+    /// GPS there is nothing to check against.
+    pub async fn verify_pos(&mut self) -> Result<bool, TurtError> {
+        self.moves_unverified = 0;
+        let p1 = match self.gps_locate().await {
+            Ok(p) => p,
+            Err(TurtError::NoGps) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let tracked = self.fstore_nav.p.clone();
+        if (tracked.x, tracked.y, tracked.z) == (p1.x, p1.y, p1.z) {
+            return Ok(false);
         }
+        self.turt.emit(TurtEventKind::Drift { tracked, actual: (&p1).into() });
+        self.turt.update_status(|s| s.pos = None);
+        self.located = false;
+        let p = self.find_heading(&p1).await?;
+        self.fstore_nav.p = p;
+        self.located = true;
+        self.pos_save()?;
+        Ok(true)
     }
 
-    fn ignore_err<T, E>(_: Result<T, E>) -> () {}
+    /// Where the turtle ends up and which way it faces, after looking for a free block to step into
+    /// around it, and then around the blocks above and below it. `None` if it is boxed in.
+    async fn probe_heading(&mut self, p1: &PosH) -> Result<Option<PosH>, TurtError> {
+        if let Some(p) = self.probe_sides(p1).await? {
+            return Ok(Some(p));
+        }
+        for (dir, back, dy) in [(TurtDir::Up, TurtDir::Down, 1), (TurtDir::Down, TurtDir::Up, -1)] {
+            if self.turt.detect(dir).await? || !self.turt.mv(dir).await?.success() {
+                continue;
+            }
+            let level = PosH { y: p1.y + dy, ..p1.clone() };
+            let found = self.probe_sides(&level).await?;
+            let returned = self.turt.mv(back).await?.success();
+            match (found, returned) {
+                (Some(p), true) => return Ok(Some(PosH { y: p1.y, ..p })),
+                (Some(p), false) => return Ok(Some(p)),
+                (None, _) => (),
+            }
+        }
+        Ok(None)
+    }
 
-    pub fn turn_head(&mut self, h: Head) {
+    /// Turns until there is a free block in front, steps into it and back. The heading follows from
+    /// where GPS puts the turtle before and after. `None` if there is no way to go on any side.
+    async fn probe_sides(&mut self, p1: &PosH) -> Result<Option<PosH>, TurtError> {
+        for _ in 0..4 {
+            if !self.turt.detect(TurtDir::Forw).await? && self.turt.mv_forw().await?.success() {
+                let p2 = self.gps_locate().await;
+                let returned = self.turt.mv_back().await?.success();
+                let p2 = p2?;
+                let h = heading_between(p1, &p2);
+                return Ok(Some(match returned {
+                    true => p1.clone().with_head(h),
+                    false => p2.with_head(h),
+                }));
+            }
+            if let Some(f) = self.turt.turn_right().await?.failure() {
+                return Err(self.move_failed(f));
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn turn_head(&mut self, h: Head) -> Result<(), TurtError> {
         let r = self.fstore_nav.p.h.diff(&h);
         for _ in 0..r.abs() {
             match r < 0 {
-                true => self.turn_left(),
-                false => self.turn_right(),
+                true => self.turn_left().await?,
+                false => self.turn_right().await?,
             };
         }
         self.fstore_nav.p.h = h;
-        self.pos_save();
+        self.pos_save()
     }
 
-    pub fn turn_left(&mut self) {
-        self.fstore_nav.p.h = match self.fstore_nav.p.h {
+    pub async fn turn_left(&mut self) -> Result<(), TurtError> {
+        let h = match self.fstore_nav.p.h {
             Head::N => Head::W,
             Head::E => Head::N,
             Head::S => Head::E,
             Head::W => Head::S,
         };
-        Self::ignore_err(self.turt.turn_left());
-        self.pos_save();
+        self.intend(self.fstore_nav.p.clone().with_head(h.clone()))?;
+        let m = self.turt.turn_left().await?;
+        self.turned(m, h)
     }
 
-    pub fn turn_right(&mut self) {
-        self.fstore_nav.p.h = match self.fstore_nav.p.h {
+    pub async fn turn_right(&mut self) -> Result<(), TurtError> {
+        let h = match self.fstore_nav.p.h {
             Head::N => Head::E,
             Head::E => Head::S,
             Head::S => Head::W,
             Head::W => Head::N,
         };
-        Self::ignore_err(self.turt.turn_right());
-        self.pos_save();
+        self.intend(self.fstore_nav.p.clone().with_head(h.clone()))?;
+        let m = self.turt.turn_right().await?;
+        self.turned(m, h)
     }
 
-    fn avoid_turtle(
-        &mut self,
-        inspect: &TurtInspect,
-        dig_func: TurtFunc<'a, anyhow::Result<TurtMovement>>,
-    ) {
-        if let Some(b) = inspect.block() {
-            if !self.avoid_other_turtles {
-                Self::ignore_err(dig_func(self.turt));
-            } else {
-                if b == "computercraft:turtle_normal" {
-                    let mut rng = rand::thread_rng();
-                    if rng.gen_range(0..2) == 0 {
-                        self.mv_up();
-                        self.mv_forw();
-                        self.mv_down();
-                    }
-                } else {
-                    Self::ignore_err(dig_func(self.turt));
-                }
+    /// Only takes on the new heading once the turtle has actually turned.
+    fn turned(&mut self, m: TurtMovement, h: Head) -> Result<(), TurtError> {
+        if let Some(f) = m.failure() {
+            return Err(self.move_failed(f));
+        }
+        self.fstore_nav.p.h = h;
+        self.pos_save()?;
+        self.intent_done();
+        Ok(())
+    }
+
+    fn move_failed(&mut self, failure: MoveFailure) -> TurtError {
+        self.intent_done();
+        if failure == MoveFailure::OutOfFuel {
+            self.set_fuel(TurtFuel::Level(0));
+        }
+        failure.into_error()
+    }
+
+    async fn avoid_turtle(&mut self, inspect: &TurtInspect, dir: TurtDir) -> Result<(), TurtError> {
+        match inspect.block() {
+            // Turtles that hold a reservation were already waited for. This one isn't driven by the
+            // server, or hasn't been located yet, so it gets some time to move on.
+            Some(b) if self.avoid_other_turtles && is_turtle(b, inspect.tags()) => {
+                time::sleep(RESERVATION_WAIT).await
             }
+            Some(_) => self.dig_through(dir, inspect).await?,
+            None => (),
         }
+        Ok(())
     }
 
-    pub fn mv_forw(&mut self) {
-        loop {
-            match self.turt.insp_forw() {
-                Ok(i) => {
-                    self.avoid_turtle(&i, TurtControl::dig_forw);
+    /// Claims `p` before moving into it, waiting while another turtle holds it. Gives up after
+    /// `move_attempts` tries, or straight away if the turtles are waiting on each other and this one
+    /// has to give way.
+    async fn reserve(&self, p: &Pos) -> Result<(), TurtError> {
+        if !self.avoid_other_turtles || !self.located {
+            return Ok(());
+        }
+        let mut failure = MoveFailure::Unknown;
+        for _ in 0..self.turt.config().move_attempts.max(1) {
+            let claim = self.turt.reservations().lock().unwrap().claim(self.turtleid, p);
+            match claim {
+                Claim::Claimed => return Ok(()),
+                Claim::Held(holder) => {
+                    failure = MoveFailure::Reserved(holder);
+                    time::sleep(RESERVATION_WAIT).await;
                 }
-                Err(_) => continue,
-            }
-            match self.turt.mv_forw() {
-                Ok(m) => {
-                    if m.success() {
-                        break;
-                    }
+                Claim::Deadlock(holder) => {
+                    failure = MoveFailure::Reserved(holder);
+                    break;
                 }
-                Err(_) => continue,
             }
         }
-        match self.fstore_nav.p.h {
-            Head::N => self.fstore_nav.p.z -= 1,
-            Head::E => self.fstore_nav.p.x += 1,
-            Head::S => self.fstore_nav.p.z += 1,
-            Head::W => self.fstore_nav.p.x -= 1,
-        }
-        self.pos_save();
+        self.turt.reservations().lock().unwrap().stop_waiting(self.turtleid);
+        Err(TurtError::MovementBlocked(failure))
     }
 
-    pub fn mv_back(&mut self) {
-        match self.turt.mv_back() {
-            Ok(m) => {
-                if !m.success() {
-                    return;
-                }
+    /// Gives up the claim on `p` after failing to move into it.
+    fn release(&self, p: &Pos) {
+        self.turt.reservations().lock().unwrap().release(self.turtleid, p);
+    }
+
+    /// Only fails if the block can't or mustn't ever be dug, otherwise moving tells whether the way
+    /// is clear.
+    async fn dig_through(&mut self, dir: TurtDir, inspect: &TurtInspect) -> Result<(), TurtError> {
+        if let Some(b) = inspect.block() {
+            if self.nav_conf.never_digs(b, inspect.tags()) {
+                return Err(TurtError::MovementBlocked(MoveFailure::NeverDig(b.clone())));
             }
-            Err(_) => return,
         }
-        match self.fstore_nav.p.h {
-            Head::N => self.fstore_nav.p.z += 1,
-            Head::E => self.fstore_nav.p.x -= 1,
-            Head::S => self.fstore_nav.p.z -= 1,
-            Head::W => self.fstore_nav.p.x += 1,
+        match self.turt.dig(dir).await {
+            Ok(m) => match m.failure() {
+                Some(f) if f.is_permanent() => Err(self.move_failed(f)),
+                _ => Ok(()),
+            },
+            Err(e) if e.is_connection() => Err(e),
+            Err(_) => Ok(()),
         }
-        self.pos_save();
     }
 
-    pub fn mv_up(&mut self) {
-        loop {
-            match self.turt.insp_up() {
+    /// Blocks that scripts mustn't dig either, for `TurtControl::dig_unless`.
+    pub fn never_dig(&self) -> Vec<String> {
+        self.nav_conf.never_dig_patterns()
+    }
+
+    /// Gets out of the way of a turtle it was deadlocked with.
+    async fn sidestep(&mut self) -> Result<(), TurtError> {
+        match self.mv_up().await {
+            Err(TurtError::MovementBlocked(MoveFailure::Reserved(_))) => self.mv_down().await,
+            r => r,
+        }
+    }
+
+    /// The block in front, above or below the turtle.
+    fn target(&self, dir: TurtDir) -> Pos {
+        self.fstore_nav.p.neighbour(dir)
+    }
+
+    /// Clears the way in `dir` and tries to move, up to `move_attempts` times. Gives up straight away
+    /// if trying again can't help, e.g. because of bedrock or running out of fuel.
+    async fn mv_dir(&mut self, dir: TurtDir) -> Result<(), TurtError> {
+        self.try_mv_dir(dir, true).await?;
+        Ok(())
+    }
+
+    /// Same as `mv_dir`, but if `dig` isn't set it returns `false` instead of digging through a block
+    /// in the way. Always moves if `dig` is set.
+    async fn try_mv_dir(&mut self, dir: TurtDir, dig: bool) -> Result<bool, TurtError> {
+        let target = self.target(dir);
+        self.reserve(&target).await?;
+        let r = self.try_mv_dir_reserved(dir, dig).await;
+        if !matches!(r, Ok(true)) {
+            self.release(&target);
+        }
+        r
+    }
+
+    async fn try_mv_dir_reserved(&mut self, dir: TurtDir, dig: bool) -> Result<bool, TurtError> {
+        let mut failure = MoveFailure::Unknown;
+        for _ in 0..self.turt.config().move_attempts.max(1) {
+            match self.turt.insp(dir).await {
                 Ok(i) => {
-                    self.avoid_turtle(&i, TurtControl::dig_up);
+                    if !dig && i.block().is_some() {
+                        return Ok(false);
+                    }
+                    self.avoid_turtle(&i, dir).await?;
                 }
+                Err(e) if e.is_connection() => return Err(e),
                 Err(_) => continue,
             }
-            match self.turt.mv_up() {
-                Ok(m) => {
-                    if m.success() {
-                        break;
+            let to: PosH = self.target(dir).into();
+            self.intend(to.with_head(self.fstore_nav.p.h.clone()))?;
+            match self.turt.mv(dir).await {
+                Ok(m) => match m.failure() {
+                    None => return Ok(true),
+                    Some(MoveFailure::OutOfFuel) => return Err(self.move_failed(MoveFailure::OutOfFuel)),
+                    Some(f) if f.is_permanent() => return Err(self.move_failed(f)),
+                    Some(f) => {
+                        self.intent_done();
+                        failure = f;
                     }
-                }
+                },
+                Err(e) if e.is_connection() => return Err(e),
                 Err(_) => continue,
             }
         }
-        self.fstore_nav.p.y += 1;
-        self.pos_save();
+        Err(TurtError::MovementBlocked(failure))
+    }
+
+    /// Updates the position after moving in `dir`.
+    fn pos_step(&mut self, dir: TurtDir) -> Result<(), TurtError> {
+        let p = self.target(dir);
+        self.fstore_nav.p.x = p.x;
+        self.fstore_nav.p.y = p.y;
+        self.fstore_nav.p.z = p.z;
+        self.pos_moved()
+    }
+
+    pub async fn mv_forw(&mut self) -> Result<(), TurtError> {
+        self.mv_dir(TurtDir::Forw).await?;
+        self.pos_step(TurtDir::Forw)
+    }
+
+    pub async fn mv_back(&mut self) -> Result<(), TurtError> {
+        let mut p = self.fstore_nav.p.clone();
+        match p.h {
+            Head::N => p.z += 1,
+            Head::E => p.x -= 1,
+            Head::S => p.z -= 1,
+            Head::W => p.x += 1,
+        }
+        let behind: Pos = (&p).into();
+        self.reserve(&behind).await?;
+        if let Err(e) = self.intend(p.clone()) {
+            self.release(&behind);
+            return Err(e);
+        }
+        let failure = match self.turt.mv_back().await {
+            Ok(m) => m.failure(),
+            Err(e) => {
+                self.release(&behind);
+                return Err(e);
+            }
+        };
+        if let Some(f) = failure {
+            self.release(&behind);
+            return Err(self.move_failed(f));
+        }
+        self.fstore_nav.p = p;
+        self.pos_moved()
+    }
+
+    pub async fn mv_up(&mut self) -> Result<(), TurtError> {
+        self.mv_dir(TurtDir::Up).await?;
+        self.pos_step(TurtDir::Up)
+    }
+
+    pub async fn mv_down(&mut self) -> Result<(), TurtError> {
+        self.mv_dir(TurtDir::Down).await?;
+        self.pos_step(TurtDir::Down)
     }
 
-    pub fn mv_down(&mut self) {
+    /// Turns towards `next`, which has to be next to the turtle. Returns which way to move.
+    async fn face(&mut self, next: &Pos) -> Result<TurtDir, TurtError> {
+        let p = self.pos();
+        let h = match (next.x - p.x, next.y - p.y, next.z - p.z) {
+            (0, 1, 0) => return Ok(TurtDir::Up),
+            (0, -1, 0) => return Ok(TurtDir::Down),
+            (1, 0, 0) => Head::E,
+            (-1, 0, 0) => Head::W,
+            (0, 0, 1) => Head::S,
+            (0, 0, -1) => Head::N,
+            _ => unreachable!("{} is not next to {}", next, p),
+        };
+        self.turn_head(h).await?;
+        Ok(TurtDir::Forw)
+    }
+
+    /// Goes to `dst` along a route planned around what the turtles have seen so far, digging only
+    /// where the route has to. Plans again whenever it runs into a block it didn't know about.
+    pub async fn goto_path(&mut self, dst: &Pos) -> Result<(), TurtError> {
+        // Plans that didn't get the turtle anywhere
+        let mut stuck = 0;
+        let mut failure = MoveFailure::Obstructed;
+        let area = pathfinding::search_area(&self.pos().into(), dst, &self.nav_conf);
         loop {
-            match self.turt.insp_down() {
-                Ok(i) => {
-                    self.avoid_turtle(&i, TurtControl::dig_down);
-                }
-                Err(_) => continue,
+            let start: Pos = self.pos().into();
+            let occupied = match self.avoid_other_turtles {
+                true => self.turt.reservations().lock().unwrap().held_by_others(self.turtleid),
+                false => Default::default(),
+            };
+            let path = pathfinding::find_path(&self.turt.map().read().unwrap(), &start, dst, &area, &occupied, &self.nav_conf)
+                .ok_or_else(|| TurtError::NoPath(dst.clone()))?;
+            if path.is_empty() {
+                return Ok(());
             }
-            match self.turt.mv_down() {
-                Ok(m) => {
-                    if m.success() {
+
+            for next in path.iter() {
+                let dig = matches!(self.turt.map().read().unwrap().get(next), Some(Cell::Block(_) | Cell::Solid));
+                let dir = self.face(next).await?;
+                match self.try_mv_dir(dir, dig).await {
+                    Ok(true) => {
+                        self.pos_step(dir)?;
+                        stuck = 0;
+                    }
+                    // There's a block the plan didn't know about, it's on the map now
+                    Ok(false) => break,
+                    // If it's precious or can't be dug, that's on the map now as well. If another
+                    // turtle is in the way, the route goes around it next time.
+                    Err(TurtError::MovementBlocked(f)) => {
+                        failure = f;
                         break;
                     }
+                    Err(e) => return Err(e),
                 }
-                Err(_) => continue,
+            }
+            stuck += 1;
+            if stuck > self.turt.config().move_attempts {
+                return Err(TurtError::MovementBlocked(failure));
             }
         }
-        self.fstore_nav.p.y -= 1;
-        self.pos_save();
     }
 
-    pub fn goto_head(&mut self, dst: &PosH, order: Order) {
-        self.goto_nohead(&dst.into(), order);
-        self.turn_head(dst.h.clone());
+    pub async fn goto_head(&mut self, dst: &PosH, order: Order) -> Result<(), TurtError> {
+        // Boxed, because refueling goes through here again
+        Box::pin(self.goto_nohead(&dst.into(), order)).await?;
+        self.turn_head(dst.h.clone()).await
     }
 
-    pub fn goto_nohead(&mut self, dst: &Pos, order: Order) {
+    /// Checks the position with GPS first if the turtle has made `verify_every` moves since it was
+    /// last checked, see `verify_pos`.
+    ///
+    /// Refuels on the way if a fuel depot is set and the turtle is running low. Uses `goto_path` if
+    /// pathfinding is turned on, in which case `order` is ignored, or once going one axis at a time
+    /// runs into a block that can't or mustn't be dug.
+    pub async fn goto_nohead(&mut self, dst: &Pos, order: Order) -> Result<(), TurtError> {
+        let every = self.nav_conf.verify_every;
+        if every > 0 && self.moves_unverified >= every {
+            self.verify_pos().await?;
+        }
+        self.ensure_fuel(dst).await?;
+        if self.nav_conf.pathfinding {
+            return self.goto_path(dst).await;
+        }
         let order_arr = order.order_arr();
+        let mut attempts = 0;
+        loop {
+            match self.goto_axes(dst, order_arr).await {
+                // Going straight won't work, find a way around
+                Err(TurtError::MovementBlocked(
+                    MoveFailure::NeverDig(_) | MoveFailure::Unbreakable | MoveFailure::Protected,
+                )) => return self.goto_path(dst).await,
+                // Gave way to another turtle, get out of its way and try again
+                Err(TurtError::MovementBlocked(f @ MoveFailure::Reserved(_))) => {
+                    attempts += 1;
+                    if attempts >= self.turt.config().move_attempts {
+                        return Err(TurtError::MovementBlocked(f));
+                    }
+                    self.sidestep().await?;
+                }
+                r => return r,
+            }
+        }
+    }
+
+    /// Goes to `dst` one axis at a time, in `order_arr`.
+    async fn goto_axes(&mut self, dst: &Pos, order_arr: impl IntoIterator<Item = Axis>) -> Result<(), TurtError> {
         for d in order_arr {
             match d {
                 Axis::X => {
                     if self.fstore_nav.p.x < dst.x {
-                        self.turn_head(Head::E);
+                        self.turn_head(Head::E).await?;
                     } else if self.fstore_nav.p.x > dst.x {
-                        self.turn_head(Head::W);
+                        self.turn_head(Head::W).await?;
                     }
                     for _ in 0..(self.fstore_nav.p.x - dst.x).abs() as usize {
-                        self.mv_forw();
+                        self.mv_forw().await?;
                     }
                 }
                 Axis::Y => {
                     if self.fstore_nav.p.y < dst.y {
                         for _ in 0..(self.fstore_nav.p.y - dst.y).abs() as usize {
-                            self.mv_up()
+                            self.mv_up().await?;
                         }
                     } else if self.fstore_nav.p.y > dst.y {
                         for _ in 0..(self.fstore_nav.p.y - dst.y).abs() as usize {
-                            self.mv_down()
+                            self.mv_down().await?;
                         }
                     };
                 }
                 Axis::Z => {
                     if self.fstore_nav.p.z < dst.z {
-                        self.turn_head(Head::S);
+                        self.turn_head(Head::S).await?;
                     } else if self.fstore_nav.p.z > dst.z {
-                        self.turn_head(Head::N);
+                        self.turn_head(Head::N).await?;
                     }
                     for _ in 0..(self.fstore_nav.p.z - dst.z).abs() as usize {
-                        self.mv_forw();
+                        self.mv_forw().await?;
                     }
                 }
             }
         }
-        if self.avoid_other_turtles {
-            if self.fstore_nav.p.x != dst.x || self.fstore_nav.p.y != dst.y || self.fstore_nav.p.z != dst.z {
-                self.goto_nohead(&dst, order);
-            }
-        }
+        Ok(())
     }
 }
@@ -0,0 +1,227 @@
+//! Plans routes with A* over what the turtles know of the world, see `WorldMap`. Air is cheapest,
+//! blocks that haven't been seen yet are assumed to be air but cost a bit more, and digging costs the
+//! most. Blocks that mustn't or can't be dug are never part of a route.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use super::navigation::Pos;
+use super::world_map::{Cell, WorldMap};
+
+const AIR_COST: u32 = 1;
+const UNKNOWN_COST: u32 = 2;
+/// Other turtles usually move out of the way.
+const TURTLE_COST: u32 = 4;
+const DIG_COST: u32 = 8;
+/// Turtles, and the computers, modems and chests that turtles and players rely on.
+const DEFAULT_NEVER_DIG: &[&str] = &[
+    "#computercraft:turtle",
+    "computercraft:turtle_*",
+    "#computercraft:computer",
+    "computercraft:computer_*",
+    "computercraft:wireless_modem_*",
+    "computercraft:wired_modem*",
+    "computercraft:monitor_*",
+    "#forge:chests",
+    "#c:chests",
+    "minecraft:chest",
+    "minecraft:trapped_chest",
+    "minecraft:ender_chest",
+    "minecraft:barrel",
+    "*shulker_box",
+];
+
+/// Read from the `navigation` table of a job.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct NavConfig {
+    /// Plan routes around known blocks, instead of going one axis at a time and digging through
+    /// everything in the way.
+    #[serde(default)]
+    pub pathfinding: bool,
+    /// Blocks that are never dug, as block IDs or tags starting with `#`. Either can contain `*`,
+    /// e.g. `computercraft:turtle_*`. Defaults to turtles, computers, modems and chests.
+    #[serde(default = "NavConfig::default_never_dig")]
+    pub never_dig: Vec<String>,
+    /// Never dug either, on top of `never_dig`, e.g. ores or parts of builds.
+    #[serde(default)]
+    pub precious: Vec<String>,
+    /// How far a route can stray outside of the box between where the turtle set off and its
+    /// destination.
+    #[serde(default = "NavConfig::default_search_margin")]
+    pub search_margin: i64,
+    /// Stops looking for a route after this many blocks.
+    #[serde(default = "NavConfig::default_max_nodes")]
+    pub max_nodes: usize,
+    /// How many moves the turtle makes before its position is checked with GPS, the next time it
+    /// sets off somewhere. 0 to never check.
+    #[serde(default = "NavConfig::default_verify_every")]
+    pub verify_every: usize,
+}
+
+impl NavConfig {
+    fn default_search_margin() -> i64 {
+        16
+    }
+
+    fn default_max_nodes() -> usize {
+        200_000
+    }
+
+    fn default_verify_every() -> usize {
+        64
+    }
+
+    fn default_never_dig() -> Vec<String> {
+        DEFAULT_NEVER_DIG.iter().map(|b| b.to_string()).collect()
+    }
+
+    /// Whether `block` with `tags` must be left alone.
+    pub fn never_digs(&self, block: &str, tags: &[String]) -> bool {
+        matches_block(&self.never_dig_patterns(), block, tags)
+    }
+
+    /// `never_dig` and `precious` together, for the turtle to check itself, see
+    /// `TurtCommand::DigUnless`.
+    pub fn never_dig_patterns(&self) -> Vec<String> {
+        self.never_dig.iter().chain(self.precious.iter()).cloned().collect()
+    }
+
+    /// What it costs to go into a block, `None` if it can't be entered.
+    fn cost(&self, map: &WorldMap, cell: Option<&Cell>) -> Option<u32> {
+        match cell {
+            None => Some(UNKNOWN_COST),
+            Some(Cell::Air) => Some(AIR_COST),
+            Some(Cell::Blocked) => None,
+            Some(Cell::Block(b)) if is_turtle(b, map.tags(b)) => Some(TURTLE_COST),
+            Some(Cell::Block(b)) if self.never_digs(b, map.tags(b)) => None,
+            Some(Cell::Block(_) | Cell::Solid) => Some(DIG_COST),
+        }
+    }
+}
+
+impl Default for NavConfig {
+    fn default() -> Self {
+        Self {
+            pathfinding: false,
+            never_dig: Self::default_never_dig(),
+            precious: vec![],
+            search_margin: Self::default_search_margin(),
+            max_nodes: Self::default_max_nodes(),
+            verify_every: Self::default_verify_every(),
+        }
+    }
+}
+
+/// Whether `block` with `tags` is a turtle, which can be waited for to move out of the way.
+/// Matched the same way as in `DEFAULT_NEVER_DIG`, so e.g. turtle eggs aren't taken for turtles.
+pub fn is_turtle(block: &str, tags: &[String]) -> bool {
+    block.starts_with("computercraft:turtle_") || tags.iter().any(|t| t == "computercraft:turtle")
+}
+
+/// Whether `block` with `tags` matches one of `patterns`, block IDs or tags starting with `#`.
+pub fn matches_block(patterns: &[String], block: &str, tags: &[String]) -> bool {
+    patterns.iter().any(|p| match p.strip_prefix('#') {
+        Some(tag) => tags.iter().any(|t| glob(tag, t)),
+        None => glob(p, block),
+    })
+}
+
+/// Whether `s` matches `pattern`, in which `*` stands for any number of characters.
+fn glob(pattern: &str, s: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == s;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !s.starts_with(first) || !s[first.len()..].ends_with(last) {
+        return false;
+    }
+    let mut rest = &s[first.len()..s.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+pub type Key = (i64, i64, i64);
+
+fn key(p: &Pos) -> Key {
+    (p.x, p.y, p.z)
+}
+
+fn heuristic(a: Key, b: Key) -> u32 {
+    (a.0.abs_diff(b.0) + a.1.abs_diff(b.1) + a.2.abs_diff(b.2)) as u32 * AIR_COST
+}
+
+/// Corners of the area routes from `a` to `b` are looked for in. It stays the same while the turtle
+/// is on its way, so that planning again can't take it ever further away.
+pub fn search_area(a: &Pos, b: &Pos, conf: &NavConfig) -> (Pos, Pos) {
+    let m = conf.search_margin;
+    (
+        Pos::new(a.x.min(b.x) - m, a.y.min(b.y) - m, a.z.min(b.z) - m),
+        Pos::new(a.x.max(b.x) + m, a.y.max(b.y) + m, a.z.max(b.z) + m),
+    )
+}
+
+/// The blocks to go through to get from `start` to `dst`, not including `start`. Goes around the
+/// blocks in `occupied` except for `dst`, e.g. blocks other turtles are in. `None` if there is no
+/// route within `area`, see `search_area`.
+pub fn find_path(
+    map: &WorldMap,
+    start: &Pos,
+    dst: &Pos,
+    area: &(Pos, Pos),
+    occupied: &HashSet<Key>,
+    conf: &NavConfig,
+) -> Option<Vec<Pos>> {
+    let (start, dst) = (key(start), key(dst));
+    let (min, max) = (key(&area.0), key(&area.1));
+    let in_bounds = |k: Key| {
+        (min.0..=max.0).contains(&k.0) && (min.1..=max.1).contains(&k.1) && (min.2..=max.2).contains(&k.2)
+    };
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<Key, Key> = HashMap::new();
+    let mut best: HashMap<Key, u32> = HashMap::new();
+    open.push(Reverse((heuristic(start, dst), 0, start)));
+    best.insert(start, 0);
+
+    while let Some(Reverse((_, g, k))) = open.pop() {
+        if k == dst {
+            let mut path = vec![];
+            let mut k = k;
+            while k != start {
+                path.push(Pos::new(k.0, k.1, k.2));
+                k = came_from[&k];
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if g > best[&k] {
+            continue;
+        }
+        if best.len() > conf.max_nodes {
+            return None;
+        }
+
+        for (dx, dy, dz) in [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)] {
+            let n = (k.0 + dx, k.1 + dy, k.2 + dz);
+            if !in_bounds(n) || (n != dst && occupied.contains(&n)) {
+                continue;
+            }
+            let cost = match conf.cost(map, map.get(&Pos::new(n.0, n.1, n.2))) {
+                Some(cost) => cost,
+                None => continue,
+            };
+            let g = g + cost;
+            if best.get(&n).is_none_or(|b| *b > g) {
+                best.insert(n, g);
+                came_from.insert(n, k);
+                open.push(Reverse((g + heuristic(n, dst), g, n)));
+            }
+        }
+    }
+    None
+}
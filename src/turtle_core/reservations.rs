@@ -0,0 +1,122 @@
+//! Keeps turtles out of each other's way. Every turtle holds the block it is in, and claims the
+//! block it is about to move into. A turtle that wants a block another turtle holds waits for it, or
+//! gives way if the turtles are waiting on each other.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::TurtleIdentifier;
+use super::navigation::Pos;
+use super::pathfinding::Key;
+
+pub type SharedReservations = Arc<Mutex<Reservations>>;
+
+fn key(p: &Pos) -> Key {
+    (p.x, p.y, p.z)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    Claimed,
+    /// Held by another turtle, try again later.
+    Held(TurtleIdentifier),
+    /// Held by another turtle that is, in the end, waiting on this one. This turtle has to give way.
+    Deadlock(TurtleIdentifier),
+}
+
+#[derive(Debug, Default)]
+pub struct Reservations {
+    /// Which turtle holds each block.
+    cells: HashMap<Key, TurtleIdentifier>,
+    /// The block each turtle is waiting for.
+    waiting: HashMap<TurtleIdentifier, Key>,
+}
+
+impl Reservations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn shared(self) -> SharedReservations {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn holder(&self, p: &Pos) -> Option<TurtleIdentifier> {
+        self.cells.get(&key(p)).copied()
+    }
+
+    /// Claims `p` for `turtle` on top of the blocks it already holds. If another turtle holds it,
+    /// `turtle` is counted as waiting for it until it claims a block or calls `stop_waiting`.
+    pub fn claim(&mut self, turtle: TurtleIdentifier, p: &Pos) -> Claim {
+        let k = key(p);
+        match self.cells.get(&k).copied() {
+            Some(holder) if holder != turtle => {
+                self.waiting.insert(turtle, k);
+                match self.gives_way(turtle) {
+                    true => Claim::Deadlock(holder),
+                    false => Claim::Held(holder),
+                }
+            }
+            _ => {
+                self.cells.insert(k, turtle);
+                self.waiting.remove(&turtle);
+                Claim::Claimed
+            }
+        }
+    }
+
+    /// Whether `turtle` is part of a loop of turtles waiting on each other and has to give way,
+    /// which is up to the one with the highest ID.
+    fn gives_way(&self, turtle: TurtleIdentifier) -> bool {
+        let mut t = turtle;
+        let mut highest = turtle;
+        let mut seen = HashSet::new();
+        loop {
+            let holder = match self.waiting.get(&t).and_then(|k| self.cells.get(k)) {
+                Some(holder) => *holder,
+                None => return false,
+            };
+            if holder == turtle {
+                return highest == turtle;
+            }
+            // A loop that `turtle` isn't part of
+            if !seen.insert(holder) {
+                return false;
+            }
+            highest = highest.max(holder);
+            t = holder;
+        }
+    }
+
+    pub fn stop_waiting(&mut self, turtle: TurtleIdentifier) {
+        self.waiting.remove(&turtle);
+    }
+
+    /// Gives up a block that `turtle` claimed but didn't move into.
+    pub fn release(&mut self, turtle: TurtleIdentifier, p: &Pos) {
+        if self.cells.get(&key(p)) == Some(&turtle) {
+            self.cells.remove(&key(p));
+        }
+    }
+
+    /// `turtle` is now at `p`, and only holds that block.
+    pub fn occupy(&mut self, turtle: TurtleIdentifier, p: &Pos) {
+        self.cells.retain(|_, t| *t != turtle);
+        self.cells.insert(key(p), turtle);
+        self.waiting.remove(&turtle);
+    }
+
+    /// `turtle` doesn't hold any blocks any more, e.g. because it isn't known where it is.
+    pub fn forget(&mut self, turtle: TurtleIdentifier) {
+        self.cells.retain(|_, t| *t != turtle);
+        self.waiting.remove(&turtle);
+    }
+
+    /// Blocks held by turtles other than `turtle`, which routes should go around.
+    pub fn held_by_others(&self, turtle: TurtleIdentifier) -> HashSet<Key> {
+        self.cells
+            .iter()
+            .filter(|(_, t)| **t != turtle)
+            .map(|(k, _)| *k)
+            .collect()
+    }
+}
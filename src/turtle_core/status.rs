@@ -0,0 +1,69 @@
+//! Snapshot of what the server knows about a turtle, shared between its worker and the web server.
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use crate::jobs::JobId;
+use crate::server::SessionId;
+use crate::TurtleIdentifier;
+use super::data::{TurtFuel, TurtSlot};
+use super::navigation::PosH;
+
+pub type SharedStatus = Arc<RwLock<TurtStatus>>;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "script", rename_all = "snake_case")]
+pub enum JobProgress {
+    ChunkDigger {
+        layer: usize,
+        layers: usize,
+        stack_count: usize,
+    },
+    ModelBuilder {
+        layer: usize,
+        layers: usize,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TurtStatus {
+    pub id: TurtleIdentifier,
+    pub session: Option<SessionId>,
+    pub pos: Option<PosH>,
+    pub job: Option<JobId>,
+    pub last_cmd: Option<String>,
+    /// False while the turtle isn't answering commands.
+    pub reachable: bool,
+    /// Milliseconds since the unix epoch.
+    pub last_response: Option<u64>,
+    pub inventory: Vec<Option<TurtSlot>>,
+    pub fuel: Option<TurtFuel>,
+    pub progress: Option<JobProgress>,
+}
+
+impl TurtStatus {
+    pub fn new(id: TurtleIdentifier) -> Self {
+        Self {
+            id,
+            session: None,
+            pos: None,
+            job: None,
+            last_cmd: None,
+            reachable: true,
+            last_response: None,
+            inventory: vec![],
+            fuel: None,
+            progress: None,
+        }
+    }
+
+    pub fn shared(id: TurtleIdentifier) -> SharedStatus {
+        Arc::new(RwLock::new(Self::new(id)))
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
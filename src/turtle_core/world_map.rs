@@ -0,0 +1,232 @@
+//! What the turtles have seen of the world. Every inspect, dig, place and move is recorded by
+//! `TurtControl`, so all turtles share one map of what has been mined and built.
+//!
+//! The map is split into chunks of 16x16x16 blocks, each saved to its own file in `MAP_DIR` once it
+//! has changed, see `WorldMap::save`. The tags of every kind of block that was inspected are kept
+//! alongside, in `TAGS_FILE`.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use rocket::serde::json::serde_json;
+use serde::{Deserialize, Serialize};
+
+use crate::TurtleIdentifier;
+use super::file_system_storage::write_atomically;
+use super::navigation::Pos;
+use super::status::now_millis;
+
+pub const MAP_DIR: &str = "world_map";
+const TAGS_FILE: &str = "tags.json";
+const CHUNK_SIZE: i64 = 16;
+
+pub type SharedWorldMap = Arc<RwLock<WorldMap>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Cell {
+    Air,
+    Block(String),
+    /// There is a block, but it isn't known which, e.g. one a turtle placed.
+    Solid,
+    /// Can't be dug, e.g. bedrock or a protected area.
+    Blocked,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Observation {
+    pub cell: Cell,
+    /// Milliseconds since the unix epoch.
+    pub time: u64,
+    /// Which turtle saw it.
+    pub turtle: TurtleIdentifier,
+}
+
+/// One block of the map, as it is saved and exported.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MapEntry {
+    #[serde(flatten)]
+    pub pos: Pos,
+    #[serde(flatten)]
+    pub obs: Observation,
+}
+
+type Key = (i64, i64, i64);
+
+#[derive(Debug, Default)]
+struct Chunk {
+    cells: HashMap<Key, Observation>,
+    /// Changed since it was last saved.
+    dirty: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct WorldMap {
+    chunks: HashMap<Key, Chunk>,
+    /// Tags of each kind of block.
+    tags: HashMap<String, Vec<String>>,
+    tags_dirty: bool,
+    /// Where the chunks are saved, `None` if the map is only kept in memory.
+    dir: Option<PathBuf>,
+}
+
+fn key(p: &Pos) -> Key {
+    (p.x, p.y, p.z)
+}
+
+fn chunk_key(p: &Pos) -> Key {
+    (p.x.div_euclid(CHUNK_SIZE), p.y.div_euclid(CHUNK_SIZE), p.z.div_euclid(CHUNK_SIZE))
+}
+
+fn chunk_file(dir: &Path, c: Key) -> PathBuf {
+    dir.join(format!("{}.{}.{}.json", c.0, c.1, c.2))
+}
+
+impl WorldMap {
+    /// A map that is only kept in memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the chunks saved in `dir`, which is where changes are saved to from then on. Chunks that
+    /// can't be read are left out.
+    pub fn load(dir: &Path) -> Self {
+        let mut map = Self {
+            dir: Some(dir.to_path_buf()),
+            ..Self::default()
+        };
+        let files = match std::fs::read_dir(dir) {
+            Ok(files) => files,
+            Err(_) => return map,
+        };
+        for file in files.flatten() {
+            let path = file.path();
+            // Left over from a save that didn't finish
+            if path.extension().is_some_and(|e| e != "json") {
+                continue;
+            }
+            if file.file_name() == TAGS_FILE {
+                match std::fs::read_to_string(&path).map(|d| serde_json::from_str(&d)) {
+                    Ok(Ok(tags)) => map.tags = tags,
+                    _ => println!("Skipping block tags {}", path.display()),
+                }
+                continue;
+            }
+            let entries = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|d| serde_json::from_str::<Vec<MapEntry>>(&d).map_err(|e| e.to_string()));
+            match entries {
+                Ok(entries) => {
+                    for e in entries {
+                        map.chunk_mut(&e.pos).cells.insert(key(&e.pos), e.obs);
+                    }
+                }
+                Err(e) => println!("Skipping map chunk {}: {}", path.display(), e),
+            }
+        }
+        map
+    }
+
+    pub fn shared(self) -> SharedWorldMap {
+        Arc::new(RwLock::new(self))
+    }
+
+    fn chunk_mut(&mut self, p: &Pos) -> &mut Chunk {
+        self.chunks.entry(chunk_key(p)).or_default()
+    }
+
+    /// `None` if no turtle has been next to it.
+    pub fn get(&self, p: &Pos) -> Option<&Cell> {
+        self.observation(p).map(|o| &o.cell)
+    }
+
+    pub fn observation(&self, p: &Pos) -> Option<&Observation> {
+        self.chunks.get(&chunk_key(p))?.cells.get(&key(p))
+    }
+
+    /// Records what `turtle` saw at `p`. Knowing there is a block doesn't replace knowing which block
+    /// it is.
+    pub fn observe(&mut self, p: &Pos, cell: Cell, turtle: TurtleIdentifier) {
+        if cell == Cell::Solid && matches!(self.get(p), Some(Cell::Block(_) | Cell::Blocked)) {
+            return;
+        }
+        let chunk = self.chunk_mut(p);
+        chunk.cells.insert(key(p), Observation { cell, time: now_millis(), turtle });
+        chunk.dirty = true;
+    }
+
+    /// Tags of the kind of block, empty if it has none or was never inspected.
+    pub fn tags(&self, block: &str) -> &[String] {
+        self.tags.get(block).map(|t| t.as_slice()).unwrap_or_default()
+    }
+
+    pub fn set_tags(&mut self, block: &str, tags: &[String]) {
+        if self.tags.get(block).map(|t| t.as_slice()) != Some(tags) {
+            self.tags.insert(block.to_string(), tags.to_vec());
+            self.tags_dirty = true;
+        }
+    }
+
+    /// Every block between the corners `a` and `b`, both included.
+    pub fn entries_in(&self, a: &Pos, b: &Pos) -> Vec<MapEntry> {
+        let (min, max) = (
+            Pos::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            Pos::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        );
+        let (cmin, cmax) = (chunk_key(&min), chunk_key(&max));
+        self.chunks
+            .iter()
+            .filter(|(c, _)| {
+                (cmin.0..=cmax.0).contains(&c.0) && (cmin.1..=cmax.1).contains(&c.1) && (cmin.2..=cmax.2).contains(&c.2)
+            })
+            .flat_map(|(_, chunk)| chunk.cells.iter())
+            .filter(|(k, _)| {
+                (min.x..=max.x).contains(&k.0) && (min.y..=max.y).contains(&k.1) && (min.z..=max.z).contains(&k.2)
+            })
+            .map(|(k, obs)| MapEntry { pos: Pos::new(k.0, k.1, k.2), obs: obs.clone() })
+            .collect()
+    }
+
+    /// Every block on the map.
+    pub fn entries(&self) -> Vec<MapEntry> {
+        self.chunks
+            .values()
+            .flat_map(|chunk| chunk.cells.iter())
+            .map(|(k, obs)| MapEntry { pos: Pos::new(k.0, k.1, k.2), obs: obs.clone() })
+            .collect()
+    }
+
+    /// Writes the chunks that changed since they were last saved. Does nothing if the map is only
+    /// kept in memory.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => return Ok(()),
+        };
+        std::fs::create_dir_all(&dir)?;
+        for (c, chunk) in self.chunks.iter_mut().filter(|(_, chunk)| chunk.dirty) {
+            let entries: Vec<MapEntry> = chunk.cells
+                .iter()
+                .map(|(k, obs)| MapEntry { pos: Pos::new(k.0, k.1, k.2), obs: obs.clone() })
+                .collect();
+            let data = serde_json::to_string(&entries).map_err(std::io::Error::other)?;
+            write_atomically(&chunk_file(&dir, *c), data.as_bytes())?;
+            chunk.dirty = false;
+        }
+        if self.tags_dirty {
+            let data = serde_json::to_string(&self.tags).map_err(std::io::Error::other)?;
+            write_atomically(&dir.join(TAGS_FILE), data.as_bytes())?;
+            self.tags_dirty = false;
+        }
+        Ok(())
+    }
+
+    /// How many blocks are on the map.
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(|c| c.cells.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,182 @@
+// Top-down (x/z) map of the turtles and the bounds of their jobs.
+// Updated from the server-sent events at `/status/stream`.
+// EventSource can't send the operator token, so the stream is read with fetch instead.
+
+const JOB_COLOURS = ["#4e79a7", "#f28e2b", "#59a14f", "#e15759", "#b07aa1", "#76b7b2"];
+// Responses older than this are shown as stale.
+const STALE_MS = 10000;
+const RETRY_MS = 3000;
+const PADDING = 4;
+
+const canvas = document.getElementById("map");
+const ctx = canvas.getContext("2d");
+const tbody = document.querySelector("#turtles tbody");
+const connection = document.getElementById("connection");
+const tokenInput = document.getElementById("token");
+
+function viewBounds(snapshot) {
+    const xs = [];
+    const zs = [];
+    for (const job of snapshot.jobs) {
+        xs.push(job.bounds[0].x, job.bounds[1].x);
+        zs.push(job.bounds[0].z, job.bounds[1].z);
+    }
+    for (const turt of snapshot.turtles) {
+        if (turt.pos) {
+            xs.push(turt.pos.x);
+            zs.push(turt.pos.z);
+        }
+    }
+    if (xs.length === 0) {
+        return null;
+    }
+    return {
+        minX: Math.min(...xs) - PADDING,
+        maxX: Math.max(...xs) + PADDING,
+        minZ: Math.min(...zs) - PADDING,
+        maxZ: Math.max(...zs) + PADDING,
+    };
+}
+
+function drawTurtle(x, z, heading, size) {
+    const angle = {n: 0, e: Math.PI / 2, s: Math.PI, w: -Math.PI / 2}[heading] ?? 0;
+    ctx.save();
+    ctx.translate(x, z);
+    ctx.rotate(angle);
+    ctx.beginPath();
+    ctx.moveTo(0, -size);
+    ctx.lineTo(size * 0.7, size);
+    ctx.lineTo(-size * 0.7, size);
+    ctx.closePath();
+    ctx.fill();
+    ctx.restore();
+}
+
+function drawMap(snapshot) {
+    canvas.width = canvas.clientWidth;
+    canvas.height = canvas.clientHeight;
+    ctx.clearRect(0, 0, canvas.width, canvas.height);
+
+    const view = viewBounds(snapshot);
+    if (view === null) {
+        return;
+    }
+    const scale = Math.min(
+        canvas.width / (view.maxX - view.minX),
+        canvas.height / (view.maxZ - view.minZ),
+    );
+    const toCanvas = (x, z) => [(x - view.minX) * scale, (z - view.minZ) * scale];
+
+    for (const job of snapshot.jobs) {
+        const [x1, z1] = toCanvas(job.bounds[0].x, job.bounds[0].z);
+        const [x2, z2] = toCanvas(job.bounds[1].x + 1, job.bounds[1].z + 1);
+        ctx.strokeStyle = JOB_COLOURS[job.id % JOB_COLOURS.length];
+        ctx.setLineDash(job.started ? [] : [4, 4]);
+        ctx.strokeRect(Math.min(x1, x2), Math.min(z1, z2), Math.abs(x2 - x1), Math.abs(z2 - z1));
+        ctx.fillStyle = ctx.strokeStyle;
+        ctx.fillText(`job ${job.id} (${job.type})`, Math.min(x1, x2) + 2, Math.min(z1, z2) - 4);
+    }
+    ctx.setLineDash([]);
+
+    const size = Math.max(scale / 2, 4);
+    for (const turt of snapshot.turtles) {
+        if (!turt.pos) {
+            continue;
+        }
+        const [x, z] = toCanvas(turt.pos.x + 0.5, turt.pos.z + 0.5);
+        ctx.fillStyle = turt.job === null ? "#aaa" : JOB_COLOURS[turt.job % JOB_COLOURS.length];
+        drawTurtle(x, z, turt.pos.h, size);
+        ctx.fillStyle = "#ddd";
+        ctx.fillText(`${turt.id} (y ${turt.pos.y})`, x + size + 2, z);
+    }
+}
+
+function progressText(progress) {
+    if (!progress) {
+        return "";
+    }
+    return `${progress.script} ${progress.layer}/${progress.layers}`;
+}
+
+function drawTable(snapshot) {
+    const now = Date.now();
+    tbody.replaceChildren(...snapshot.turtles.map((turt) => {
+        const row = document.createElement("tr");
+        const pos = turt.pos ? `(${turt.pos.x}, ${turt.pos.y}, ${turt.pos.z})[${turt.pos.h}]` : "";
+        const age = turt.last_response === null ? null : now - turt.last_response;
+        const cells = [
+            turt.id,
+            pos,
+            turt.job ?? "",
+            progressText(turt.progress),
+            turt.last_cmd ?? "",
+            age === null ? "" : `${Math.round(age / 1000)}s ago`,
+        ];
+        for (const text of cells) {
+            const cell = document.createElement("td");
+            cell.textContent = text;
+            row.appendChild(cell);
+        }
+        if (age !== null && age > STALE_MS) {
+            row.classList.add("stale");
+        }
+        return row;
+    }));
+}
+
+function onMessage(message) {
+    const data = message
+        .split("\n")
+        .filter((line) => line.startsWith("data:"))
+        .map((line) => line.slice(5).replace(/^ /, ""));
+    if (data.length === 0) {
+        return;
+    }
+    const snapshot = JSON.parse(data.join("\n"));
+    drawMap(snapshot);
+    drawTable(snapshot);
+}
+
+let following = null;
+
+async function follow(token) {
+    following?.abort();
+    following = new AbortController();
+    const signal = following.signal;
+    while (!signal.aborted) {
+        try {
+            const resp = await fetch("/status/stream", {headers: {"X-Operator-Token": token}, signal});
+            if (resp.status === 401) {
+                connection.textContent = "wrong operator token";
+                return;
+            }
+            if (resp.ok) {
+                connection.textContent = "connected";
+                const reader = resp.body.pipeThrough(new TextDecoderStream()).getReader();
+                let buffer = "";
+                for (;;) {
+                    const {value, done} = await reader.read();
+                    if (done) {
+                        break;
+                    }
+                    const messages = (buffer + value).split("\n\n");
+                    buffer = messages.pop();
+                    messages.forEach(onMessage);
+                }
+            }
+        } catch (e) {
+            if (signal.aborted) {
+                return;
+            }
+        }
+        connection.textContent = "disconnected, retrying...";
+        await new Promise((resolve) => setTimeout(resolve, RETRY_MS));
+    }
+}
+
+tokenInput.value = localStorage.getItem("operatorToken") ?? "";
+tokenInput.onchange = () => {
+    localStorage.setItem("operatorToken", tokenInput.value);
+    follow(tokenInput.value);
+};
+follow(tokenInput.value);
@@ -0,0 +1,143 @@
+//! A turtle that talks to the server over HTTP the way `protu.lua` does, and runs the commands it
+//! gets against a simulated world. Also sets up simulated worlds and scripts for the tests.
+#![allow(dead_code)]
+use std::sync::{Arc, Mutex, Weak};
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::Value;
+use tempfile::TempDir;
+
+use prototurtle_rs::{init_dirs, set_data_dir};
+use prototurtle_rs::scripts::chunk_digger::{ChunkDigger, ChunkDiggerConfig};
+use prototurtle_rs::sim::{self, SharedWorld, SimWorld};
+use prototurtle_rs::turtle_core::command::TurtCommand;
+use prototurtle_rs::turtle_core::error::TurtError;
+use prototurtle_rs::turtle_core::fuel::FuelConfig;
+use prototurtle_rs::turtle_core::inventory::TurtBlock;
+use prototurtle_rs::turtle_core::navigation::{Pos, TurtNavigation};
+use prototurtle_rs::turtle_core::pathfinding::NavConfig;
+use prototurtle_rs::TurtleIdentifier;
+
+/// Saves positions, progress and the map to a temporary directory, which the tests running at the
+/// same time share. It is removed once every test has dropped what this returns.
+pub fn in_temp_dir() -> Arc<TempDir> {
+    static DIR: Mutex<Weak<TempDir>> = Mutex::new(Weak::new());
+    let mut current = DIR.lock().unwrap();
+    if let Some(dir) = current.upgrade() {
+        return dir;
+    }
+    let dir = Arc::new(tempfile::Builder::new().prefix("prototurtle-test-").tempdir().unwrap());
+    set_data_dir(dir.path());
+    init_dirs();
+    *current = Arc::downgrade(&dir);
+    dir
+}
+
+/// A simulated world with the blocks and turtles `build` puts in it.
+pub fn sim_world(build: impl FnOnce(&mut SimWorld)) -> SharedWorld {
+    let world = SimWorld::shared();
+    build(&mut world.lock().unwrap());
+    world
+}
+
+/// Digs out `p1` to `p2` with turtle `id`, which was added to `world`. The turtle is set up the way
+/// a chunk digger job sets it up, and keeps what it digs.
+pub async fn sim_digger(
+    world: &SharedWorld,
+    id: TurtleIdentifier,
+    p1: Pos,
+    p2: Pos,
+    nav_conf: NavConfig,
+    fuel: Option<FuelConfig>,
+) -> Result<(), TurtError> {
+    let turt = sim::connect(world, id);
+    let mut nav = TurtNavigation::new(id, &turt, true);
+    nav.set_fuel_config(fuel);
+    nav.set_nav_config(nav_conf);
+    nav.gps_init().await?;
+    let conf = ChunkDiggerConfig {
+        p1,
+        p2,
+        place_floor: TurtBlock::None,
+        chest_size: 27 * 64,
+        check_inv_every_n_blocks: 4,
+    };
+    ChunkDigger::init((id, 0, &turt, &mut nav), conf)?.run().await
+}
+
+pub struct LuaTurtle<'c> {
+    client: &'c Client,
+    world: SharedWorld,
+    id: TurtleIdentifier,
+    token: String,
+    pub session: String,
+}
+
+impl<'c> LuaTurtle<'c> {
+    pub fn new(client: &'c Client, world: &SharedWorld, id: TurtleIdentifier, token: &str) -> Self {
+        Self {
+            client,
+            world: world.clone(),
+            id,
+            token: token.to_string(),
+            session: String::new(),
+        }
+    }
+
+    fn token(&self) -> Header<'static> {
+        Header::new("X-Turtle-Token", self.token.clone())
+    }
+
+    pub async fn register(&mut self) -> Status {
+        let resp = self.client
+            .post(format!("/register/{}", self.id))
+            .header(self.token())
+            .dispatch()
+            .await;
+        let status = resp.status();
+        if status == Status::Ok {
+            self.session = resp.into_string().await.unwrap();
+        }
+        status
+    }
+
+    /// `None` if the request failed, which makes `protu.lua` register again.
+    pub async fn next(&self) -> Option<String> {
+        let resp = self.client
+            .get(format!("/next/{}?session={}", self.id, self.session))
+            .header(self.token())
+            .dispatch()
+            .await;
+        if resp.status() != Status::Ok {
+            return None;
+        }
+        resp.into_string().await
+    }
+
+    pub async fn cmdcomplete(&self, body: &Value) -> Status {
+        self.client
+            .post(format!("/cmdcomplete/{}?session={}", self.id, self.session))
+            .header(self.token())
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+            .await
+            .status()
+    }
+
+    /// Asks for the next command and runs it, like one iteration of the loop in `protu.lua`.
+    /// Returns the command, or `None` if the turtle should register again.
+    pub async fn step(&self) -> Option<String> {
+        let cmd = self.next().await?;
+        if cmd != "WAIT" {
+            // Also answers batches and exit, just like protu.lua
+            let out = self.world.lock().unwrap().exec(self.id, &cmd);
+            self.cmdcomplete(&out).await;
+        }
+        if TurtCommand::from_wire(&cmd) == Ok(TurtCommand::Exit) {
+            return None;
+        }
+        Some(cmd)
+    }
+}
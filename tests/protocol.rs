@@ -0,0 +1,408 @@
+mod common;
+
+use rocket::error::ErrorKind;
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::serde::json::{self, json, Value};
+use rocket::tokio::io::AsyncReadExt;
+use rocket::tokio::sync::mpsc;
+use rocket::tokio::time;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use common::{in_temp_dir, sim_world, LuaTurtle};
+use prototurtle_rs::server;
+use prototurtle_rs::turtle_core::control::{TurtControl, TurtControlConfig, TurtDir};
+use prototurtle_rs::turtle_core::data::TurtResponse;
+use prototurtle_rs::turtle_core::error::TurtError;
+use prototurtle_rs::turtle_core::events::EventBus;
+use prototurtle_rs::turtle_core::navigation::{Head, Pos, PosH};
+use prototurtle_rs::turtle_core::reservations::Reservations;
+use prototurtle_rs::turtle_core::status::TurtStatus;
+use prototurtle_rs::turtle_core::world_map::WorldMap;
+
+const TOKEN: &str = "secret";
+const OPERATOR_TOKEN: &str = "operator secret";
+
+fn config() -> Figment {
+    rocket::Config::figment()
+        .merge(("log_level", "off"))
+        .merge(("next_timeout_ms", 100))
+        .merge(("turtle_token", TOKEN))
+        .merge(("operator_token", OPERATOR_TOKEN))
+        .merge(("turtle_control.timeout_ms", 2000))
+}
+
+async fn client() -> Client {
+    Client::tracked(server::build_with(config())).await.unwrap()
+}
+
+fn operator() -> Header<'static> {
+    Header::new("X-Operator-Token", OPERATOR_TOKEN)
+}
+
+async fn post_json(client: &Client, uri: String, body: Value) -> Value {
+    let resp = client.post(uri).header(ContentType::JSON).header(operator()).body(body.to_string()).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    resp.into_json().await.unwrap_or(Value::Null)
+}
+
+async fn get_json(client: &Client, uri: &str) -> Value {
+    let resp = client.get(uri.to_string()).header(operator()).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    resp.into_json().await.unwrap()
+}
+
+/// Runs the commands turtle `id` gets until it has finished the job it was given.
+async fn run_job(client: &Client, turt: &LuaTurtle<'_>, id: usize) {
+    let mut cmds = 0;
+    loop {
+        let cmd = turt.step().await.expect("Turtle was told to register again");
+        if cmd != "WAIT" {
+            cmds += 1;
+            continue;
+        }
+        let status = get_json(client, &format!("/turtles/{}", id)).await;
+        if cmds > 0 && status["job"].is_null() {
+            break;
+        }
+        assert!(cmds < 10_000, "Job didn't finish");
+    }
+}
+
+/// Reads the next server-sent event from `stream`.
+async fn next_event(stream: &mut LocalResponse<'_>) -> Value {
+    loop {
+        let mut message = Vec::new();
+        while !message.ends_with(b"\n\n") {
+            let byte = time::timeout(Duration::from_secs(5), stream.read_u8()).await.expect("No event was sent");
+            message.push(byte.unwrap());
+        }
+        let message = String::from_utf8(message).unwrap();
+        let data = message.lines().filter_map(|l| l.strip_prefix("data:")).collect::<String>();
+        // Skips keep-alive comments
+        if !data.is_empty() {
+            return json::from_str(&data).unwrap();
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn turtle_runs_a_job_over_http() {
+    let _dir = in_temp_dir();
+    let client = client().await;
+    let world = sim_world(|w| {
+        w.fill(&Pos::new(0, 0, -2), &Pos::new(2, 2, -2), "minecraft:stone");
+        w.add_turtle(3, PosH { x: 0, y: 1, z: 0, h: Head::N });
+    });
+
+    assert_eq!(LuaTurtle::new(&client, &world, 3, "wrong").register().await, Status::Unauthorized);
+    let mut turt = LuaTurtle::new(&client, &world, 3, TOKEN);
+    assert_eq!(turt.register().await, Status::Ok);
+    assert_eq!(turt.step().await.as_deref(), Some("WAIT"));
+
+    let job = json!({
+        "turtles": [3],
+        "start_pos": { "x": 0, "y": 0, "z": 0, "h": "n" },
+        "type": "chunk_digger",
+        "depth": 2,
+        "width": 2,
+        "height": 3,
+        "place_floor": "none",
+        "chest_size": 1728,
+        "check_inv_every_n_blocks": 2,
+    });
+    let resp = client.post("/jobs").header(ContentType::JSON).body(job.to_string()).dispatch().await;
+    assert_eq!(resp.status(), Status::Unauthorized);
+    let jobid = post_json(&client, "/jobs".to_string(), job).await;
+    post_json(&client, format!("/jobs/{}/start", jobid), Value::Null).await;
+
+    run_job(&client, &turt, 3).await;
+
+    {
+        let w = world.lock().unwrap();
+        assert_eq!(w.count_blocks(&Pos::new(0, 0, -2), &Pos::new(2, 2, -2)), 0);
+        assert_eq!(w.turtle(3).unwrap().count("minecraft:stone"), 9);
+    }
+
+    // What was dug is on the map
+    assert_eq!(client.get("/map").dispatch().await.status(), Status::Unauthorized);
+    let map = get_json(&client, "/map?x1=0&y1=0&z1=-2&x2=2&y2=2&z2=-2").await;
+    let map = map.as_array().unwrap();
+    assert_eq!(map.len(), 9);
+    assert!(map.iter().all(|e| e["cell"] == "air" && e["turtle"] == 3));
+    let all = get_json(&client, "/map").await;
+    assert!(all.as_array().unwrap().len() >= 9);
+}
+
+#[rocket::async_test]
+async fn old_sessions_and_malformed_responses_are_rejected() {
+    let _dir = in_temp_dir();
+    let client = client().await;
+    let world = sim_world(|w| w.add_turtle(4, PosH::default()));
+
+    let mut old = LuaTurtle::new(&client, &world, 4, TOKEN);
+    old.register().await;
+    let mut turt = LuaTurtle::new(&client, &world, 4, TOKEN);
+    turt.register().await;
+    assert_ne!(old.session, turt.session);
+
+    assert_eq!(old.next().await, None);
+    assert_eq!(old.cmdcomplete(&json!({ "code": 0, "out": [true] })).await, Status::Conflict);
+    // Leaving the session out doesn't get around that
+    let token = Header::new("X-Turtle-Token", TOKEN);
+    assert_eq!(client.get("/next/4").header(token.clone()).dispatch().await.status(), Status::Conflict);
+    let resp = client.post("/cmdcomplete/4")
+        .header(token)
+        .header(ContentType::JSON)
+        .body(json!({ "code": 0, "out": [true] }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Conflict);
+    assert_eq!(turt.next().await.as_deref(), Some("WAIT"));
+    assert_eq!(turt.cmdcomplete(&json!({ "out": [true] })).await, Status::UnprocessableEntity);
+}
+
+#[rocket::async_test]
+async fn late_answers_are_not_mixed_up() {
+    let (next_tx, mut next_rx) = mpsc::unbounded_channel();
+    let (cmdcomplete_tx, cmdcomplete_rx) = mpsc::unbounded_channel();
+    let config = TurtControlConfig { timeout_ms: 50, ..TurtControlConfig::default() };
+    let stop = Arc::new(AtomicBool::new(false));
+    let turt = TurtControl::new(
+        next_tx, cmdcomplete_rx, TurtStatus::shared(5), EventBus::new(), config,
+        WorldMap::new().shared(), Reservations::new().shared(),
+    ).stop_when(stop.clone());
+
+    // Moving isn't sent again after timing out, and isn't handed to the turtle any more
+    assert!(matches!(turt.mv_forw().await, Err(TurtError::Timeout { .. })));
+    let forward = next_rx.try_recv().unwrap();
+    assert!(forward.is_cancelled());
+    assert!(next_rx.try_recv().is_err());
+
+    let answer = async {
+        // The answer to the move comes in late, before the answer to the next command
+        cmdcomplete_tx.send((Some(forward.seq), Ok(TurtResponse::Ok(json!([true]))))).unwrap();
+        let detect = next_rx.recv().await.unwrap();
+        cmdcomplete_tx.send((Some(detect.seq), Ok(TurtResponse::Ok(json!([false]))))).unwrap();
+    };
+    let (detected, _) = rocket::tokio::join!(turt.detect(TurtDir::Forw), answer);
+    assert!(!detected.unwrap());
+
+    // Once another worker takes over, nothing more is sent
+    stop.store(true, Ordering::Relaxed);
+    assert!(matches!(turt.mv_forw().await, Err(TurtError::Disconnected)));
+    assert!(next_rx.try_recv().is_err());
+}
+
+/// Whether the server refuses to start with `figment`.
+async fn refuses_to_start(figment: Figment) -> bool {
+    match server::build_with(figment.merge(("log_level", "off"))).ignite().await {
+        Ok(_) => false,
+        // Looking at the error keeps it from panicking when dropped
+        Err(e) => matches!(e.kind(), ErrorKind::FailedFairings(_)),
+    }
+}
+
+#[rocket::async_test]
+async fn refuses_to_start_without_tokens() {
+    let _dir = in_temp_dir();
+    assert!(refuses_to_start(rocket::Config::figment()).await);
+    assert!(refuses_to_start(rocket::Config::figment().merge(("turtle_token", TOKEN))).await);
+    assert!(!refuses_to_start(rocket::Config::figment().merge(("insecure", true))).await);
+}
+
+#[rocket::async_test]
+async fn turtles_move_between_jobs_that_havent_started() {
+    let _dir = in_temp_dir();
+    let client = client().await;
+    let job = |turtles: Value| json!({
+        "turtles": turtles,
+        "start_pos": { "x": 0, "y": 0, "z": 0, "h": "n" },
+        "type": "chunk_digger",
+        "depth": 1,
+        "width": 1,
+        "height": 1,
+        "place_floor": "none",
+        "chest_size": 27,
+        "check_inv_every_n_blocks": 1,
+    });
+    let assign = |turtleid: u32, jobid: &Value| {
+        client.post(format!("/turtles/{}/assign", turtleid))
+            .header(ContentType::JSON)
+            .header(operator())
+            .body(json!({ "job": jobid }).to_string())
+            .dispatch()
+    };
+    let a = post_json(&client, "/jobs".to_string(), job(json!([6, 7]))).await;
+    let b = post_json(&client, "/jobs".to_string(), job(json!([]))).await;
+
+    assert_eq!(assign(6, &b).await.status(), Status::Ok);
+    let jobs = get_json(&client, "/jobs").await;
+    assert_eq!(jobs[0]["turtles"], json!([7]));
+    assert_eq!(jobs[1]["turtles"], json!([6]));
+
+    post_json(&client, format!("/jobs/{}/start", a), Value::Null).await;
+    post_json(&client, format!("/jobs/{}/start", a), Value::Null).await;
+    assert_eq!(assign(6, &a).await.status(), Status::Conflict);
+    assert_eq!(assign(7, &b).await.status(), Status::Ok);
+    assert_eq!(assign(6, &json!(9)).await.status(), Status::NotFound);
+    // The started job keeps its turtles, so their areas don't change
+    let jobs = get_json(&client, "/jobs").await;
+    assert_eq!(jobs[0]["turtles"], json!([7]));
+    assert_eq!(jobs[1]["turtles"], json!([6, 7]));
+}
+
+#[rocket::async_test]
+async fn dashboard_finds_its_assets() {
+    let _dir = in_temp_dir();
+    let client = client().await;
+    let resp = client.get("/dashboard").dispatch().await;
+    assert_eq!(resp.status(), Status::PermanentRedirect);
+    assert_eq!(resp.headers().get_one("Location"), Some("/dashboard/"));
+    assert_eq!(client.get("/dashboard/").dispatch().await.status(), Status::Ok);
+    assert_eq!(client.get("/dashboard/dashboard.css").dispatch().await.status(), Status::Ok);
+    // The page is public, what it shows isn't
+    assert_eq!(client.get("/status/stream").dispatch().await.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn events_say_which_turtle_did_what_and_where() {
+    let _dir = in_temp_dir();
+    let client = client().await;
+    let world = sim_world(|w| {
+        w.fill(&Pos::new(32, 0, -1), &Pos::new(32, 0, -1), "minecraft:stone");
+        w.add_turtle(8, PosH { x: 32, y: 1, z: 0, h: Head::N });
+    });
+
+    assert_eq!(client.get("/events").dispatch().await.status(), Status::Unauthorized);
+    let mut events = client.get("/events").header(operator()).dispatch().await;
+    assert_eq!(events.status(), Status::Ok);
+
+    let mut turt = LuaTurtle::new(&client, &world, 8, TOKEN);
+    turt.register().await;
+    let job = json!({
+        "turtles": [8],
+        "start_pos": { "x": 32, "y": 0, "z": 0, "h": "n" },
+        "type": "chunk_digger",
+        "depth": 1,
+        "width": 1,
+        "height": 1,
+        "place_floor": "none",
+        "chest_size": 1728,
+        "check_inv_every_n_blocks": 2,
+    });
+    let jobid = post_json(&client, "/jobs".to_string(), job).await;
+    post_json(&client, format!("/jobs/{}/start", jobid), Value::Null).await;
+    run_job(&client, &turt, 8).await;
+
+    let registered = next_event(&mut events).await;
+    assert_eq!(registered["kind"], "registered");
+    assert_eq!(registered["turtle"], 8);
+    assert!(registered["timestamp"].as_u64().unwrap() > 0);
+
+    let moved = loop {
+        let event = next_event(&mut events).await;
+        if event["kind"] == "moved" {
+            break event;
+        }
+    };
+    assert_eq!(moved["turtle"], 8);
+    assert!(moved["timestamp"].as_u64().unwrap() >= registered["timestamp"].as_u64().unwrap());
+    assert_eq!(moved["pos"]["x"], 32);
+    assert!(moved["pos"]["h"].is_string());
+    let moves = ["turtle.forward()", "turtle.back()", "turtle.up()", "turtle.down()"];
+    assert!(moves.contains(&moved["cmd"].as_str().unwrap()), "{}", moved["cmd"]);
+}
+
+#[rocket::async_test]
+async fn status_shows_what_turtles_are_doing() {
+    let _dir = in_temp_dir();
+    let client = client().await;
+    let world = sim_world(|w| {
+        w.fill(&Pos::new(64, 0, -2), &Pos::new(66, 5, -2), "minecraft:stone");
+        w.add_turtle(9, PosH { x: 64, y: 1, z: 0, h: Head::N });
+    });
+
+    assert_eq!(client.get("/status").dispatch().await.status(), Status::Unauthorized);
+    let mut turt = LuaTurtle::new(&client, &world, 9, TOKEN);
+    turt.register().await;
+    let job = json!({
+        "turtles": [9],
+        "start_pos": { "x": 64, "y": 0, "z": 0, "h": "n" },
+        "type": "chunk_digger",
+        "depth": 2,
+        "width": 2,
+        "height": 6,
+        "place_floor": "none",
+        "chest_size": 1728,
+        "check_inv_every_n_blocks": 1,
+    });
+    let jobid = post_json(&client, "/jobs".to_string(), job).await;
+    post_json(&client, format!("/jobs/{}/start", jobid), Value::Null).await;
+    // Looked at after every command, since a finished job leaves no progress behind
+    let mut seen = vec![];
+    loop {
+        let cmd = turt.step().await.unwrap();
+        let status = get_json(&client, "/turtles/9").await;
+        if cmd == "WAIT" && status["job"].is_null() {
+            break;
+        }
+        assert_eq!(status["job"], jobid);
+        assert_eq!(status["reachable"], true);
+        seen.push(status);
+    }
+    let layer_done = json!({ "script": "chunk_digger", "layer": 1, "layers": 2, "stack_count": 0 });
+    assert!(seen.iter().any(|s| s["progress"] == layer_done));
+    assert!(seen.iter().any(|s| s["inventory"][0]["name"] == "minecraft:stone"));
+    let responses = seen.iter().map(|s| s["last_response"].as_u64().unwrap()).collect::<Vec<_>>();
+    assert!(responses.windows(2).all(|w| w[0] <= w[1]));
+
+    let statuses = get_json(&client, "/status").await;
+    let status = statuses.as_array().unwrap().iter().find(|s| s["id"] == 9).unwrap();
+    assert_eq!(status["session"].to_string(), turt.session);
+    let pos = world.lock().unwrap().turtle(9).unwrap().pos.clone();
+    assert_eq!(status["pos"]["x"], pos.x);
+    assert_eq!(status["pos"]["y"], pos.y);
+    assert_eq!(status["pos"]["z"], pos.z);
+    assert!(status["last_cmd"].as_str().unwrap().starts_with("turtle."));
+}
+
+#[rocket::async_test]
+async fn next_waits_for_a_command() {
+    let _dir = in_temp_dir();
+    let client = Client::tracked(server::build_with(config().merge(("next_timeout_ms", 500)))).await.unwrap();
+    let world = sim_world(|w| w.add_turtle(10, PosH { x: 96, y: 0, z: 0, h: Head::N }));
+    let mut turt = LuaTurtle::new(&client, &world, 10, TOKEN);
+    turt.register().await;
+
+    // Nothing to do, answered once the timeout runs out
+    let start = time::Instant::now();
+    assert_eq!(turt.next().await.as_deref(), Some("WAIT"));
+    assert!(start.elapsed() >= Duration::from_millis(500));
+
+    // A job comes in while the turtle is waiting, it gets the first command right away
+    let job = json!({
+        "turtles": [10],
+        "start_pos": { "x": 96, "y": 0, "z": 0, "h": "n" },
+        "type": "chunk_digger",
+        "depth": 1,
+        "width": 1,
+        "height": 1,
+        "place_floor": "none",
+        "chest_size": 1728,
+        "check_inv_every_n_blocks": 2,
+    });
+    let start = time::Instant::now();
+    let start_job = async {
+        time::sleep(Duration::from_millis(100)).await;
+        let jobid = post_json(&client, "/jobs".to_string(), job).await;
+        post_json(&client, format!("/jobs/{}/start", jobid), Value::Null).await;
+    };
+    let (cmd, _) = rocket::tokio::join!(turt.next(), start_job);
+    assert_ne!(cmd.as_deref(), Some("WAIT"));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
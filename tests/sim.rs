@@ -0,0 +1,453 @@
+mod common;
+
+use common::{in_temp_dir, sim_digger, sim_world};
+use modelutils_rs::coords::Order;
+use prototurtle_rs::data_path;
+use prototurtle_rs::sim::{self, SimStack};
+use prototurtle_rs::turtle_core::command::TurtCommand;
+use prototurtle_rs::turtle_core::control::{TurtControlConfig, TurtDir};
+use prototurtle_rs::turtle_core::data::{MoveFailure, TurtFuel};
+use prototurtle_rs::turtle_core::error::TurtError;
+use prototurtle_rs::turtle_core::events::{EventBus, TurtEventKind};
+use prototurtle_rs::turtle_core::fuel::{self, FuelConfig};
+use prototurtle_rs::turtle_core::navigation::{Head, Pos, PosH, TurtNavigation, NAV_DIR};
+use prototurtle_rs::turtle_core::pathfinding::NavConfig;
+use prototurtle_rs::turtle_core::reservations::Reservations;
+use prototurtle_rs::turtle_core::world_map::{Cell, WorldMap, MAP_DIR};
+
+#[rocket::async_test]
+async fn commands_change_the_world() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.add_turtle(0, PosH { x: 0, y: 64, z: 0, h: Head::N });
+        w.set_block(&Pos::new(0, 64, -1), "minecraft:stone");
+        w.put_in_chest(&Pos::new(0, 63, 0), SimStack::new("minecraft:cobblestone", 10));
+    });
+    let turt = sim::connect(&world, 0);
+
+    assert_eq!(turt.insp_forw().await.unwrap().block().as_deref(), Some("minecraft:stone"));
+    assert!(!turt.mv_forw().await.unwrap().success());
+
+    assert!(turt.suck_down().await.unwrap().success());
+    assert!(turt.dig_forw().await.unwrap().success());
+    assert!(turt.mv_forw().await.unwrap().success());
+
+    let cobble = turt.inv_item_detail(0).await.unwrap().unwrap();
+    assert_eq!((cobble.name(), cobble.count()), ("minecraft:cobblestone", 10));
+    let stone = turt.inv_item_detail(1).await.unwrap().unwrap();
+    assert_eq!((stone.name(), stone.count()), ("minecraft:stone", 1));
+    assert!(turt.inv_item_detail(2).await.unwrap().is_none());
+    assert!(matches!(turt.inv_select(16).await, Err(TurtError::InvalidSlot(16))));
+
+    assert!(turt.place_forw().await.unwrap().success());
+    assert_eq!(world.lock().unwrap().block(&Pos::new(0, 64, -2)), Some("minecraft:cobblestone"));
+
+    // Left alone without inspecting it first
+    let never = vec!["minecraft:cobble*".to_string()];
+    let dug = turt.dig_unless(TurtDir::Forw, &never).await.unwrap();
+    assert_eq!(dug.failure(), Some(MoveFailure::NeverDig("minecraft:cobblestone".to_string())));
+    assert_eq!(world.lock().unwrap().block(&Pos::new(0, 64, -2)), Some("minecraft:cobblestone"));
+
+    // Stops at the dig, so the turtle doesn't move
+    let resps = turt.batch().stop_on_fail().dig_up().add(TurtCommand::Forward).run().await.unwrap();
+    assert_eq!(resps.len(), 1);
+    assert!(!resps[0].success());
+    assert_eq!(world.lock().unwrap().turtle(0).unwrap().pos.z, -1);
+
+    // Sent as data, not spliced into Lua
+    let msg = "\")  os.shutdown()  print(\"";
+    turt.print(msg).await.unwrap();
+    assert_eq!(world.lock().unwrap().printed, vec![(0, msg.to_string())]);
+}
+
+#[rocket::async_test]
+async fn fuel_and_inventory_commands() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.fuel_limit = Some(1000);
+        w.add_turtle(3, PosH { x: 0, y: 64, z: 0, h: Head::N });
+        w.set_block(&Pos::new(0, 65, -1), "minecraft:stone");
+        w.give(3, SimStack::new("minecraft:coal", 5));
+        w.give(3, SimStack::new("minecraft:crafting_table", 1));
+    });
+    let turt = sim::connect(&world, 3);
+
+    assert_eq!(turt.fuel_level().await.unwrap(), TurtFuel::Level(0));
+    assert!(!turt.mv_forw().await.unwrap().success());
+    assert!(turt.refuel(Some(2)).await.unwrap().success());
+    assert_eq!(turt.fuel_level().await.unwrap(), TurtFuel::Level(160));
+    assert_eq!(turt.fuel_limit().await.unwrap(), TurtFuel::Level(1000));
+    assert!(turt.mv_forw().await.unwrap().success());
+    assert_eq!(turt.fuel_level().await.unwrap(), TurtFuel::Level(159));
+
+    assert_eq!(turt.inv_item_count(0).await.unwrap(), 3);
+    assert_eq!(turt.inv_item_space(0).await.unwrap(), 61);
+    assert!(turt.inv_transfer_to(5, Some(1)).await.unwrap().success());
+    assert_eq!(turt.inv_item_count(5).await.unwrap(), 1);
+    assert!(turt.inv_compare_to(5).await.unwrap());
+    assert!(!turt.inv_compare_to(1).await.unwrap());
+
+    assert!(turt.detect(TurtDir::Up).await.unwrap());
+    assert!(!turt.detect(TurtDir::Down).await.unwrap());
+    assert!(!turt.compare(TurtDir::Forw).await.unwrap());
+    assert!(!turt.attack(TurtDir::Forw).await.unwrap().success());
+
+    // Crafting needs a crafting table
+    assert!(matches!(turt.craft(None).await, Err(TurtError::BadReq(_))));
+    turt.inv_select(1).await.unwrap();
+    assert_eq!(turt.inv_selected_slot().await.unwrap(), 1);
+    assert!(turt.equip_left().await.unwrap().success());
+    assert_eq!(turt.inv_item_count(1).await.unwrap(), 0);
+    assert!(!turt.craft(None).await.unwrap().success());
+}
+
+#[rocket::async_test]
+async fn chunk_digger_refuels_at_the_depot() {
+    let _dir = in_temp_dir();
+    let (p1, p2) = (Pos::new(20, 0, 10), Pos::new(22, 3, 12));
+    let depot = PosH { x: 20, y: 1, z: 16, h: Head::N };
+    let world = sim_world(|w| {
+        w.fuel_limit = Some(1000);
+        w.fill(&Pos::new(20, 0, 10), &Pos::new(22, 2, 12), "minecraft:stone");
+        w.put_in_chest(&Pos::new(20, 0, 16), SimStack::new("minecraft:coal", 64));
+        w.add_turtle(4, PosH { x: 20, y: 1, z: 14, h: Head::N });
+        w.turtle_mut(4).unwrap().fuel = 20;
+    });
+    let fuel = FuelConfig { depot, threshold: 5, refuel_to: 200 };
+    sim_digger(&world, 4, p1, p2, NavConfig::default(), Some(fuel)).await.unwrap();
+
+    let w = world.lock().unwrap();
+    assert_eq!(w.count_blocks(&Pos::new(20, 0, 10), &Pos::new(22, 2, 12)), 0);
+    let coal: u32 = w.chest(&Pos::new(20, 0, 16)).unwrap().iter().flatten().map(|s| s.count).sum();
+    // Only what it takes to get to `refuel_to`, the rest stays in the depot
+    assert!((58..64).contains(&coal));
+    assert_eq!(w.turtle(4).unwrap().count("minecraft:coal"), 0);
+}
+
+#[rocket::async_test]
+async fn out_of_fuel_is_an_error() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.fuel_limit = Some(1000);
+        w.add_turtle(5, PosH { x: 0, y: 64, z: 0, h: Head::N });
+        w.put_in_chest(&Pos::new(0, 63, 0), SimStack::new("minecraft:cobblestone", 10));
+    });
+    let turt = sim::connect(&world, 5);
+    let mut nav = TurtNavigation::new(5, &turt, false);
+    assert!(matches!(nav.mv_forw().await, Err(TurtError::OutOfFuel)));
+
+    // A depot with nothing to burn doesn't keep the turtle waiting
+    let refueled = fuel::refuel_from_chest(&turt, 100).await;
+    assert!(matches!(refueled, Err(TurtError::NotFuel(item)) if item == "minecraft:cobblestone"));
+    assert_eq!(world.lock().unwrap().turtle(5).unwrap().count("minecraft:cobblestone"), 0);
+}
+
+#[rocket::async_test]
+async fn blocked_moves_give_up() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.add_turtle(6, PosH { x: 0, y: 1, z: 0, h: Head::N });
+        w.set_block(&Pos::new(0, 0, 0), "minecraft:bedrock");
+        w.add_turtle(7, PosH { x: 0, y: 1, z: -1, h: Head::N });
+    });
+    let turt = sim::connect(&world, 6);
+    let mut nav = TurtNavigation::new(6, &turt, true);
+
+    assert!(matches!(nav.mv_down().await, Err(TurtError::MovementBlocked(MoveFailure::Unbreakable))));
+    // The other turtle never moves out of the way, and going over it isn't allowed
+    world.lock().unwrap().set_block(&Pos::new(0, 2, 0), "minecraft:bedrock");
+    assert!(matches!(nav.mv_forw().await, Err(TurtError::MovementBlocked(_))));
+    assert_eq!(world.lock().unwrap().turtle(6).unwrap().pos.z, 0);
+}
+
+#[rocket::async_test]
+async fn pathfinding_goes_around_precious_blocks() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        // A glass wall with a gap at one end
+        w.fill(&Pos::new(30, 0, 5), &Pos::new(36, 4, 5), "minecraft:glass");
+        w.remove_block(&Pos::new(34, 2, 5));
+        w.add_turtle(8, PosH { x: 32, y: 2, z: 9, h: Head::N });
+    });
+    let turt = sim::connect(&world, 8);
+    let mut nav = TurtNavigation::new(8, &turt, false);
+    nav.gps_init().await.unwrap();
+    nav.set_nav_config(NavConfig {
+        pathfinding: true,
+        precious: vec!["minecraft:glass".to_string()],
+        search_margin: 2,
+        ..NavConfig::default()
+    });
+
+    let dst = Pos::new(32, 2, 2);
+    nav.goto_nohead(&dst, Order::XYZ).await.unwrap();
+    assert_eq!((nav.pos().x, nav.pos().y, nav.pos().z), (32, 2, 2));
+    assert_eq!(world.lock().unwrap().count_blocks(&Pos::new(30, 0, 5), &Pos::new(36, 4, 5)), 34);
+
+    // Closing the gap leaves no way back
+    world.lock().unwrap().set_block(&Pos::new(34, 2, 5), "minecraft:glass");
+    assert!(matches!(nav.goto_path(&Pos::new(32, 2, 8)).await, Err(TurtError::NoPath(_))));
+}
+
+#[rocket::async_test]
+async fn drift_is_corrected_with_gps() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| w.add_turtle(16, PosH { x: 20, y: 70, z: 20, h: Head::N }));
+    let events = EventBus::new();
+    let mut rx = events.subscribe();
+    let turt = sim::connect_with(
+        &world, 16, events, TurtControlConfig::default(), WorldMap::new().shared(), Reservations::new().shared(),
+    );
+    let mut nav = TurtNavigation::new(16, &turt, false);
+    nav.set_nav_config(NavConfig { verify_every: 2, ..NavConfig::default() });
+    nav.gps_init().await.unwrap();
+    assert!(!nav.verify_pos().await.unwrap());
+
+    // The turtle moved without the server hearing of it
+    world.lock().unwrap().turtle_mut(16).unwrap().pos = PosH { x: 22, y: 70, z: 20, h: Head::N };
+    assert!(nav.verify_pos().await.unwrap());
+    assert_eq!((nav.pos().x, nav.pos().y, nav.pos().z), (22, 70, 20));
+    assert!(matches!(nav.pos().h, Head::N));
+    let drifted = std::iter::from_fn(|| rx.try_recv().ok())
+        .any(|e| matches!(e.kind, TurtEventKind::Drift { actual, .. } if (actual.x, actual.z) == (22, 20)));
+    assert!(drifted);
+
+    // Checked again on the way once it has made enough moves
+    nav.goto_nohead(&Pos::new(22, 70, 18), Order::XYZ).await.unwrap();
+    world.lock().unwrap().turtle_mut(16).unwrap().pos = PosH { x: 24, y: 70, z: 18, h: Head::N };
+    nav.goto_nohead(&Pos::new(24, 70, 16), Order::XYZ).await.unwrap();
+    let t = world.lock().unwrap().turtle(16).unwrap().pos.clone();
+    assert_eq!((t.x, t.y, t.z), (24, 70, 16));
+}
+
+#[rocket::async_test]
+async fn interrupted_moves_are_recovered() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.fuel_limit = Some(100);
+        // Both went forward, but the server stopped before saving where they ended up
+        w.add_turtle(17, PosH { x: 30, y: 70, z: 31, h: Head::S });
+        w.add_turtle(18, PosH { x: 35, y: 70, z: 30, h: Head::E });
+        w.turtle_mut(18).unwrap().fuel = 9;
+        w.add_turtle(19, PosH { x: 40, y: 70, z: 40, h: Head::N });
+        w.turtle_mut(19).unwrap().fuel = 10;
+    });
+    let nav_file = |id: u32, ext: &str| data_path(NAV_DIR).join(format!("{}.{}", id, ext));
+    std::fs::write(nav_file(17, "nav"), "30\n70\n30\ns\n").unwrap();
+    std::fs::write(nav_file(17, "intent"), "30\n70\n30\ns\n30\n70\n31\ns\n\n").unwrap();
+    // Cut short while it was being written
+    std::fs::write(nav_file(18, "nav"), "34\n7").unwrap();
+    std::fs::write(nav_file(18, "intent"), "34\n70\n30\ne\n35\n70\n30\ne\n10\n").unwrap();
+
+    let turt = sim::connect(&world, 17);
+    let mut nav = TurtNavigation::new(17, &turt, false);
+    nav.gps_init().await.unwrap();
+    assert_eq!((nav.pos().x, nav.pos().y, nav.pos().z), (30, 70, 31));
+    assert!(matches!(nav.pos().h, Head::S));
+    assert!(!nav_file(17, "intent").exists());
+
+    // Without GPS, the fuel it used up gives it away
+    world.lock().unwrap().gps = false;
+    let turt = sim::connect(&world, 18);
+    let mut nav = TurtNavigation::new(18, &turt, false);
+    assert_eq!((nav.pos().x, nav.pos().z), (34, 30));
+    nav.gps_init().await.unwrap();
+    assert_eq!((nav.pos().x, nav.pos().y, nav.pos().z), (35, 70, 30));
+    assert!(matches!(nav.pos().h, Head::E));
+    assert!(!nav_file(18, "intent").exists());
+    assert_eq!(std::fs::read_to_string(nav_file(18, "nav")).unwrap(), "35\n70\n30\ne\n");
+
+    // Moves that went through leave nothing behind
+    nav.mv_forw().await.unwrap();
+    nav.turn_left().await.unwrap();
+    assert!(!nav_file(18, "intent").exists());
+
+    // A position that can't be written down is an error, rather than something to carry on without
+    world.lock().unwrap().gps = true;
+    std::fs::create_dir_all(nav_file(19, "nav")).unwrap();
+    let turt = sim::connect(&world, 19);
+    let mut nav = TurtNavigation::new(19, &turt, false);
+    assert!(matches!(nav.gps_init().await, Err(TurtError::Storage(_))));
+}
+
+#[rocket::async_test]
+async fn turtles_share_what_they_see() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.add_turtle(9, PosH { x: 40, y: 64, z: 0, h: Head::N });
+        w.add_turtle(10, PosH { x: 44, y: 64, z: 0, h: Head::N });
+        w.set_block(&Pos::new(40, 64, -1), "minecraft:stone");
+        w.set_block(&Pos::new(44, 65, 0), "minecraft:dirt");
+    });
+    let dir = data_path(MAP_DIR);
+    let map = WorldMap::load(&dir).shared();
+    let reservations = Reservations::new().shared();
+    let connect = |id| {
+        sim::connect_with(&world, id, EventBus::new(), TurtControlConfig::default(), map.clone(), reservations.clone())
+    };
+    let (a, b) = (connect(9), connect(10));
+
+    // Nothing is recorded until the turtle's position is known
+    a.insp_forw().await.unwrap();
+    assert!(map.read().unwrap().is_empty());
+
+    a.update_status(|s| s.pos = Some(PosH { x: 40, y: 64, z: 0, h: Head::N }));
+    b.update_status(|s| s.pos = Some(PosH { x: 44, y: 64, z: 0, h: Head::N }));
+    a.insp_forw().await.unwrap();
+    assert_eq!(map.read().unwrap().get(&Pos::new(40, 64, -1)), Some(&Cell::Block("minecraft:stone".to_string())));
+    a.dig_forw().await.unwrap();
+    assert_eq!(map.read().unwrap().get(&Pos::new(40, 64, -1)), Some(&Cell::Air));
+
+    b.batch().dig_up().place_up().run().await.unwrap();
+    let above = map.read().unwrap().observation(&Pos::new(44, 65, 0)).cloned().unwrap();
+    assert_eq!((above.cell, above.turtle), (Cell::Solid, 10));
+
+    map.write().unwrap().save().unwrap();
+    let loaded = WorldMap::load(&dir);
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded.get(&Pos::new(40, 64, -1)), Some(&Cell::Air));
+    assert_eq!(loaded.entries_in(&Pos::new(44, 60, 0), &Pos::new(44, 70, 0)).len(), 1);
+}
+
+#[rocket::async_test]
+async fn turtles_pass_each_other() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.add_turtle(11, PosH { x: 50, y: 64, z: 0, h: Head::E });
+        w.add_turtle(12, PosH { x: 54, y: 64, z: 0, h: Head::W });
+    });
+    let reservations = Reservations::new().shared();
+    let connect = |id| {
+        let map = WorldMap::new().shared();
+        sim::connect_with(&world, id, EventBus::new(), TurtControlConfig::default(), map, reservations.clone())
+    };
+    let (a, b) = (connect(11), connect(12));
+    let (mut nav_a, mut nav_b) = (TurtNavigation::new(11, &a, true), TurtNavigation::new(12, &b, true));
+    nav_a.gps_init().await.unwrap();
+    nav_b.gps_init().await.unwrap();
+    assert_eq!(reservations.lock().unwrap().holder(&Pos::new(54, 64, 0)), Some(12));
+
+    // Head on, so one of them has to give way
+    let (dst_a, dst_b) = (Pos::new(54, 64, 0), Pos::new(50, 64, 0));
+    let (ra, rb) = rocket::tokio::join!(
+        nav_a.goto_nohead(&dst_a, Order::XYZ),
+        nav_b.goto_nohead(&dst_b, Order::XYZ),
+    );
+    ra.unwrap();
+    rb.unwrap();
+    let w = world.lock().unwrap();
+    assert_eq!(w.turtle(11).unwrap().pos.x, 54);
+    assert_eq!(w.turtle(12).unwrap().pos.x, 50);
+}
+
+#[rocket::async_test]
+async fn never_dig_blocks_are_left_alone() {
+    let _dir = in_temp_dir();
+    let (p1, p2) = (Pos::new(60, 0, 10), Pos::new(62, 3, 12));
+    let world = sim_world(|w| {
+        w.fill(&Pos::new(60, 0, 10), &Pos::new(62, 2, 12), "minecraft:stone");
+        w.set_block(&Pos::new(61, 2, 11), "minecraft:chest");
+        w.set_block(&Pos::new(61, 0, 12), "minecraft:diamond_ore");
+        w.tags.insert("minecraft:diamond_ore".to_string(), vec!["c:ores".to_string()]);
+        w.add_turtle(13, PosH { x: 60, y: 1, z: 14, h: Head::N });
+    });
+    let nav_conf = NavConfig { precious: vec!["#c:ores".to_string()], ..NavConfig::default() };
+    sim_digger(&world, 13, p1, p2, nav_conf, None).await.unwrap();
+    {
+        let w = world.lock().unwrap();
+        assert_eq!(w.count_blocks(&Pos::new(60, 0, 10), &Pos::new(62, 2, 12)), 2);
+        assert_eq!(w.block(&Pos::new(61, 2, 11)), Some("minecraft:chest"));
+        assert_eq!(w.block(&Pos::new(61, 0, 12)), Some("minecraft:diamond_ore"));
+    }
+
+    // Going one axis at a time finds a way around instead. The position is read back from the file.
+    world.lock().unwrap().set_block(&Pos::new(60, 0, 8), "minecraft:chest");
+    let turt = sim::connect(&world, 13);
+    let mut nav = TurtNavigation::new(13, &turt, true);
+    nav.goto_nohead(&Pos::new(60, 0, 6), Order::XYZ).await.unwrap();
+    assert_eq!((nav.pos().x, nav.pos().y, nav.pos().z), (60, 0, 6));
+    assert_eq!(world.lock().unwrap().block(&Pos::new(60, 0, 8)), Some("minecraft:chest"));
+
+    // Not a turtle, so it's dug instead of waited for
+    world.lock().unwrap().set_block(&Pos::new(60, 0, 5), "minecraft:turtle_egg");
+    nav.goto_nohead(&Pos::new(60, 0, 5), Order::XYZ).await.unwrap();
+    assert_eq!(world.lock().unwrap().block(&Pos::new(60, 0, 5)), None);
+}
+
+#[rocket::async_test]
+async fn gps_init_finds_heading() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.add_turtle(1, PosH { x: 5, y: 70, z: 5, h: Head::E });
+        // Boxed in on every side, but not above
+        w.add_turtle(14, PosH { x: 8, y: 70, z: 8, h: Head::S });
+        for (x, z) in [(9, 8), (7, 8), (8, 9), (8, 7)] {
+            w.set_block(&Pos::new(x, 70, z), "minecraft:stone");
+        }
+        w.set_block(&Pos::new(8, 69, 8), "minecraft:stone");
+        w.add_turtle(15, PosH { x: 12, y: 70, z: 12, h: Head::W });
+    });
+    let turt = sim::connect(&world, 1);
+    let mut nav = TurtNavigation::new(1, &turt, false);
+
+    // Steps forward and back
+    nav.gps_init().await.unwrap();
+    assert_eq!((nav.pos().x, nav.pos().y, nav.pos().z), (5, 70, 5));
+    assert!(matches!(nav.pos().h, Head::E));
+    assert_eq!(world.lock().unwrap().turtle(1).unwrap().pos.x, 5);
+
+    let boxed = sim::connect(&world, 14);
+    let mut boxed_nav = TurtNavigation::new(14, &boxed, false);
+    boxed_nav.gps_init().await.unwrap();
+    assert_eq!((boxed_nav.pos().x, boxed_nav.pos().y, boxed_nav.pos().z), (8, 70, 8));
+    assert!(matches!(boxed_nav.pos().h, Head::S));
+    assert_eq!(world.lock().unwrap().count_blocks(&Pos::new(7, 69, 7), &Pos::new(9, 70, 9)), 5);
+
+    // Without GPS the saved position is trusted, if there is one
+    world.lock().unwrap().gps = false;
+    nav.gps_init().await.unwrap();
+    assert_eq!((nav.pos().x, nav.pos().y, nav.pos().z), (5, 70, 5));
+    assert!(matches!(nav.pos().h, Head::E));
+    let new = sim::connect(&world, 15);
+    assert!(matches!(TurtNavigation::new(15, &new, false).gps_init().await, Err(TurtError::NoGps)));
+}
+
+#[rocket::async_test]
+async fn boxed_in_new_turtles_dig_to_find_their_heading() {
+    let _dir = in_temp_dir();
+    let world = sim_world(|w| {
+        w.fill(&Pos::new(69, 69, 69), &Pos::new(71, 71, 71), "minecraft:stone");
+        w.remove_block(&Pos::new(70, 70, 70));
+        w.add_turtle(20, PosH { x: 70, y: 70, z: 70, h: Head::W });
+    });
+    let events = EventBus::new();
+    let mut rx = events.subscribe();
+    let turt = sim::connect_with(
+        &world, 20, events, TurtControlConfig::default(), WorldMap::new().shared(), Reservations::new().shared(),
+    );
+    let mut nav = TurtNavigation::new(20, &turt, false);
+    nav.gps_init().await.unwrap();
+    assert_eq!((nav.pos().x, nav.pos().y, nav.pos().z), (69, 70, 70));
+    assert!(matches!(nav.pos().h, Head::W));
+    assert_eq!(world.lock().unwrap().block(&Pos::new(69, 70, 70)), None);
+
+    // Digging its way out isn't a move from the made-up position, but the block is reported
+    let events: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).map(|e| e.kind).collect();
+    assert!(!events.iter().any(|e| matches!(e, TurtEventKind::Moved)));
+    assert!(events.iter().any(|e| matches!(e, TurtEventKind::DugToLocate { block: Some(b) } if b == "minecraft:stone")));
+}
+
+#[rocket::async_test]
+async fn chunk_digger_clears_its_strip() {
+    let _dir = in_temp_dir();
+    let (p1, p2) = (Pos::new(10, 0, 10), Pos::new(12, 3, 12));
+    let world = sim_world(|w| {
+        w.fill(&Pos::new(10, 0, 10), &Pos::new(12, 2, 12), "minecraft:stone");
+        w.add_turtle(2, PosH { x: 10, y: 1, z: 14, h: Head::N });
+    });
+    sim_digger(&world, 2, p1, p2, NavConfig::default(), None).await.unwrap();
+
+    let w = world.lock().unwrap();
+    assert_eq!(w.count_blocks(&Pos::new(10, 0, 10), &Pos::new(12, 2, 12)), 0);
+    assert_eq!(w.turtle(2).unwrap().count("minecraft:stone"), 27);
+}